@@ -0,0 +1,232 @@
+//! Derive macros for `master_and_servant`
+//!
+//! `#[derive(WireSize)]` computes the largest `ssmarshal` serialization of a type at compile time:
+//!
+//! - structs and tuples are the sum of their fields
+//! - enums are one byte of discriminant plus the largest variant
+//!
+//! along with the `SCHEMA` hash of the encoding: the schemas of the fields in order, and
+//! for enums the name and fields of each variant (renaming a variant changes its meaning).
+//!
+//! For non-generic types a `Frame` implementation is provided as well, giving a
+//! `FrameBuf` buffer type (and constructor) large enough for a crc checked and cobs encoded frame.
+//!
+//! The derive also enforces the `ssmarshal` invariants, rejecting at compile time:
+//!
+//! - enums without `#[repr(C)]`, or with more than 256 variants
+//! - references, raw pointers and unsized fields (slices, `str`, trait objects)
+//! - `usize`/`isize` fields, as their width differs between the master and the servant
+//!
+//! Field types hidden behind aliases are caught as well, since every field must implement `WireSize`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Data, DeriveInput, Fields, GenericArgument,
+    PathArguments, Type,
+};
+
+/// ssmarshal encodes the discriminant in a single byte
+const MAX_VARIANTS: usize = 256;
+
+#[proc_macro_derive(WireSize)]
+pub fn derive_wire_size(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    if let Err(err) = check(&input) {
+        return err.to_compile_error().into();
+    }
+
+    let size = match &input.data {
+        Data::Struct(data) => fields_size(&data.fields),
+        Data::Enum(data) => {
+            if data.variants.is_empty() {
+                // uninhabited enums are not serialized
+                quote!(0)
+            } else {
+                // `Ord::max` is not `const`
+                let max = data.variants.iter().fold(quote!(0), |max, v| {
+                    let size = fields_size(&v.fields);
+                    quote!(::master_and_servant::const_max(#max, #size))
+                });
+                quote!(1 + #max)
+            }
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(&input.ident, "unions are not supported on the wire")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let seed = |kind: &str| {
+        let kind = syn::LitByteStr::new(kind.as_bytes(), input.ident.span());
+        quote!(::master_and_servant::schema_hash(::master_and_servant::SCHEMA_SEED, #kind))
+    };
+    let schema = match &input.data {
+        Data::Struct(data) => fields_schema(seed("struct"), &data.fields),
+        Data::Enum(data) => data.variants.iter().fold(seed("enum"), |hash, v| {
+            let name = v.ident.to_string();
+            let len = v.fields.len() as u32;
+            let hash = quote! {
+                ::master_and_servant::schema_mix(
+                    ::master_and_servant::schema_hash(#hash, #name.as_bytes()),
+                    #len,
+                )
+            };
+            fields_schema(hash, &v.fields)
+        }),
+        Data::Union(_) => unreachable!(),
+    };
+
+    let generic = !input.generics.params.is_empty();
+    let type_params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::master_and_servant::WireSize));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let frame = if generic {
+        // buffer types cannot depend on generic parameters on stable Rust
+        quote!()
+    } else {
+        quote! {
+            impl ::master_and_servant::Frame for #ident {
+                type FrameBuf = [u8; <#ident as ::master_and_servant::WireSize>::MAX_FRAME_SIZE];
+
+                fn frame_buf() -> Self::FrameBuf {
+                    [0u8; <#ident as ::master_and_servant::WireSize>::MAX_FRAME_SIZE]
+                }
+            }
+        }
+    };
+
+    quote! {
+        impl #impl_generics ::master_and_servant::WireSize for #ident #ty_generics #where_clause {
+            const MAX_WIRE_SIZE: usize = #size;
+            const SCHEMA: u32 = #schema;
+        }
+
+        #frame
+    }
+    .into()
+}
+
+/// Sum of the wire sizes of the fields
+fn fields_size(fields: &Fields) -> TokenStream2 {
+    let tys = fields.iter().map(|f| &f.ty);
+    quote! {
+        (0 #(+ <#tys as ::master_and_servant::WireSize>::MAX_WIRE_SIZE)*)
+    }
+}
+
+/// Hash of the schemas of the fields, continuing from the `hash` expression
+fn fields_schema(hash: TokenStream2, fields: &Fields) -> TokenStream2 {
+    let tys = fields.iter().map(|f| &f.ty);
+    quote! {
+        {
+            let hash = #hash;
+            #(let hash = ::master_and_servant::schema_mix(
+                hash,
+                <#tys as ::master_and_servant::WireSize>::SCHEMA,
+            );)*
+            hash
+        }
+    }
+}
+
+/// Check the ssmarshal invariants, reporting all violations
+fn check(input: &DeriveInput) -> syn::Result<()> {
+    let mut errors = Vec::new();
+    match &input.data {
+        Data::Struct(data) => check_fields(&data.fields, &mut errors),
+        Data::Enum(data) => {
+            if !has_repr_c(input) {
+                errors.push(syn::Error::new_spanned(
+                    &input.ident,
+                    "wire enums must be `#[repr(C)]`",
+                ));
+            }
+            if data.variants.len() > MAX_VARIANTS {
+                errors.push(syn::Error::new_spanned(
+                    &input.ident,
+                    format!(
+                        "wire enums are limited to {} variants, found {}",
+                        MAX_VARIANTS,
+                        data.variants.len()
+                    ),
+                ));
+            }
+            for variant in &data.variants {
+                check_fields(&variant.fields, &mut errors);
+            }
+        }
+        Data::Union(_) => {}
+    }
+    match errors.into_iter().reduce(|mut acc, err| {
+        acc.combine(err);
+        acc
+    }) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+fn has_repr_c(input: &DeriveInput) -> bool {
+    let mut repr_c = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            repr_c |= meta.path.is_ident("C");
+            Ok(())
+        });
+    }
+    repr_c
+}
+
+fn check_fields(fields: &Fields, errors: &mut Vec<syn::Error>) {
+    for field in fields {
+        check_type(&field.ty, errors);
+    }
+}
+
+fn check_type(ty: &Type, errors: &mut Vec<syn::Error>) {
+    let mut error = |msg: &str| errors.push(syn::Error::new(ty.span(), msg));
+    match ty {
+        Type::Reference(_) => error("references cannot be sent over the wire"),
+        Type::Ptr(_) => error("raw pointers cannot be sent over the wire"),
+        Type::Slice(_) | Type::TraitObject(_) | Type::ImplTrait(_) => {
+            error("wire types must be `Sized`")
+        }
+        Type::Array(array) => check_type(&array.elem, errors),
+        Type::Tuple(tuple) => tuple.elems.iter().for_each(|ty| check_type(ty, errors)),
+        Type::Paren(paren) => check_type(&paren.elem, errors),
+        Type::Group(group) => check_type(&group.elem, errors),
+        Type::Path(path) => {
+            if path.qself.is_none() && path.path.is_ident("str") {
+                error("wire types must be `Sized`");
+            } else if path.path.is_ident("usize") || path.path.is_ident("isize") {
+                error("`usize`/`isize` differ in width between master and servant, use a fixed width integer");
+            }
+            for segment in &path.path.segments {
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    for arg in &args.args {
+                        if let GenericArgument::Type(ty) = arg {
+                            check_type(ty, errors);
+                        }
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,62 @@
+//! assign_sim.rs
+//!
+//! Assigning `DevId`s to simulated servants by their unique hardware ids.
+//!
+//! On host `cd master` run:
+//! cargo run --example assign_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Discovered, Error, Master, Retry};
+use master_and_servant::{Command, DevId, Uid, Value, UNASSIGNED};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+// the first two share a 24 bit prefix, each shared bit costs a silent probe
+const UIDS: [u128; 5] = [
+    0x1234_5678_9abc_def0_0000_0000_0000_0001,
+    0x1234_56f8_9abc_def0_0000_0000_0000_0001,
+    0x8000_0000_0000_0000_0000_0000_0000_0000,
+    0x0bad_cafe_0000_0000_0000_0000_0000_0000,
+    0xffff_ffff_ffff_ffff_ffff_ffff_ffff_fffe,
+];
+
+fn main() -> Result<(), Error> {
+    let mut servants: Vec<SimServant> = UIDS
+        .iter()
+        .map(|uid| SimServant::new(UNASSIGNED).with_uid(Uid::from_u128(*uid)))
+        .collect();
+    // flashed with a fixed `DevId`
+    servants.push(SimServant::new(0b0001));
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(100),
+        retries: 1,
+        backoff: 1,
+    });
+
+    let one_hot = || (0..8).map(|bit| 1 << bit as DevId);
+    let in_use: Vec<DevId> = master
+        .scan(one_hot())?
+        .into_iter()
+        .map(|discovered| match discovered {
+            Discovered::Servant(dev, _) | Discovered::Collision(dev) => dev,
+        })
+        .collect();
+    println!("in use {:?}", in_use);
+
+    let start = Instant::now();
+    let assigned = master.assign(one_hot().filter(|dev| !in_use.contains(dev)))?;
+    println!("assigned in {:?}", start.elapsed());
+    for (uid, dev) in &assigned {
+        println!("uid {:#034x} -> {:#010b}", uid.to_u128(), dev);
+    }
+
+    // the servants answer on their new `DevId`
+    for (_, dev) in &assigned {
+        master.send_to(*dev, Command::Set(0x12, Value::U32(*dev), 0))?;
+        let response = master.send_to(*dev, Command::Get(0x12, 0, 0))?;
+        println!("send_to {:#010b}, response {:?}", dev, response);
+    }
+    Ok(())
+}
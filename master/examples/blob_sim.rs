@@ -0,0 +1,55 @@
+//! blob_sim.rs
+//!
+//! Bulk transfer with a simulated servant over a lossy link: a calibration table is read
+//! into memory, replaced from a byte slice, and read back.
+//!
+//! On host `cd master` run:
+//! cargo run --example blob_sim
+//!
+use master::sim::{SimPort, SimServant, SIM_BLOB};
+use master::{Error, Master};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const LOSS: f64 = 0.05;
+const DEV: u32 = 1;
+const CALIBRATION: u32 = 7;
+
+fn main() -> Result<(), Error> {
+    let table: Vec<u8> = (0..1000u32).map(|i| (i / 4) as u8).collect();
+    let servant = SimServant::new(DEV).with_blob(CALIBRATION, table.clone());
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, vec![servant]).with_loss(LOSS));
+
+    let start = Instant::now();
+    let mut read = Vec::new();
+    let size = master.read_blob(DEV, CALIBRATION, &mut read)?;
+    println!(
+        "read {} bytes in {:.0} ms, matches: {}",
+        size,
+        start.elapsed().as_secs_f32() * 1e3,
+        read == table
+    );
+
+    let new: Vec<u8> = table.iter().rev().copied().collect();
+    let start = Instant::now();
+    master.write_blob(DEV, CALIBRATION, new.len() as u32, &new[..])?;
+    println!(
+        "wrote {} bytes in {:.0} ms, servant matches: {}",
+        new.len(),
+        start.elapsed().as_secs_f32() * 1e3,
+        master.port().servants[0].blob(CALIBRATION) == Some(&new[..])
+    );
+
+    read.clear();
+    master.read_blob(DEV, CALIBRATION, &mut read)?;
+    println!("read back matches: {}", read == new);
+
+    if let Err(err) = master.read_blob(DEV, CALIBRATION + 1, &mut read) {
+        println!("read unknown blob: {}", err);
+    }
+    if let Err(err) = master.write_blob(DEV, CALIBRATION, SIM_BLOB + 1, std::io::empty()) {
+        println!("write {} bytes: {}", SIM_BLOB + 1, err);
+    }
+    Ok(())
+}
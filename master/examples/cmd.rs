@@ -7,13 +7,13 @@
 // cargo embed --example cmd --release
 
 use master::open;
-use master_and_servant::{Command, Message, Response};
+use master_and_servant::{Command, Response, Value};
 use serial2::SerialPort;
 use std::io::Read;
 use std::mem::size_of;
 
-type InBuf = [u8; size_of::<Command>()];
-type OutBuf = [u8; size_of::<Response>()];
+type InBuf = [u8; size_of::<Response>()];
+type OutBuf = [u8; size_of::<Command>()];
 
 fn main() -> Result<(), std::io::Error> {
     let mut port = open()?;
@@ -21,7 +21,7 @@ fn main() -> Result<(), std::io::Error> {
     let mut out_buf = [0u8; size_of::<Command>()];
     let mut in_buf = [0u8; size_of::<Response>()];
 
-    let cmd = Command::Set(0x12, Message::B(12), 0b001);
+    let cmd = Command::Set(0x12, Value::U32(12), 0b001);
     println!("request {:?}", cmd);
     let response = request(&cmd, &mut port, &mut out_buf, &mut in_buf)?;
     println!("response {:?}", response);
@@ -10,7 +10,7 @@
 use corncobs::{decode_in_place, encode_buf, max_encoded_len, ZERO};
 use crc::{Crc, CRC_32_CKSUM};
 use master::open;
-use master_and_servant::{Command, Message, Response};
+use master_and_servant::{Command, Response, Value};
 use serial2::SerialPort;
 use std::io::Read;
 use std::mem::size_of;
@@ -28,7 +28,7 @@ fn main() -> Result<(), std::io::Error> {
     let mut out_buf = [0u8; OUT_SIZE];
     let mut in_buf = [0u8; IN_SIZE];
 
-    let cmd = Command::Set(0x12, Message::B(12), 0b001);
+    let cmd = Command::Set(0x12, Value::U32(12), 0b001);
     println!("request {:?}", cmd);
     let response = request(&cmd, &mut port, &mut out_buf, &mut in_buf)?;
     println!("response {:?}", response);
@@ -57,7 +57,7 @@ fn request(
     let n_crc = ssmarshal::serialize(&mut out_buf[n_cmd..], &crc).unwrap();
     println!("n_crc {}", n_crc);
 
-    let buf_copy = out_buf.clone(); // could we do better?
+    let buf_copy = *out_buf; // could we do better?
     let n = encode_buf(&buf_copy[0..n_cmd + n_crc], out_buf);
     println!("cobs n {}", n);
     let to_write = &out_buf[0..n];
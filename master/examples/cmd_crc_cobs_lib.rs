@@ -7,59 +7,33 @@
 //! On host `cd master` run:
 //! cargo run --example cmd_crc_cobs_lib
 //!
-use corncobs::{max_encoded_len, ZERO};
-use master::open;
-use master_and_servant::{deserialize_crc_cobs, serialize_crc_cobs, Command, Message, Response};
-use serial2::SerialPort;
-use std::io::Read;
-use std::mem::size_of;
+use master::{open, Error, Master};
+use master_and_servant::{Command, Value};
 
-const IN_SIZE: usize = max_encoded_len(size_of::<Response>() + size_of::<u32>());
-const OUT_SIZE: usize = max_encoded_len(size_of::<Command>() + size_of::<u32>());
+fn main() -> Result<(), Error> {
+    let mut master = Master::new(open()?);
 
-type InBuf = [u8; IN_SIZE];
-type OutBuf = [u8; OUT_SIZE];
-
-fn main() -> Result<(), std::io::Error> {
-    let mut port = open()?;
-
-    let mut out_buf = [0u8; OUT_SIZE];
-    let mut in_buf = [0u8; IN_SIZE];
-
-    let cmd = Command::Set(0x12, Message::B(12), 0b001);
+    let cmd = Command::Set(0x12, Value::U32(12), 0b001);
     println!("request {:?}", cmd);
-    let response = request(&cmd, &mut port, &mut out_buf, &mut in_buf)?;
+    let response = master.request(cmd)?;
     println!("response {:?}", response);
 
-    let cmd = Command::Get(0x12, 12, 0b001);
+    let cmd = Command::Get(0x12, 0, 0b001);
     println!("request {:?}", cmd);
-    let response = request(&cmd, &mut port, &mut out_buf, &mut in_buf)?;
+    let response = master.request(cmd)?;
     println!("response {:?}", response);
-    Ok(())
-}
-
-fn request(
-    cmd: &Command,
-    port: &mut SerialPort,
-    out_buf: &mut OutBuf,
-    in_buf: &mut InBuf,
-) -> Result<Response, std::io::Error> {
-    println!("out_buf {}", out_buf.len());
-    let to_write = serialize_crc_cobs(cmd, out_buf);
-    port.write_all(to_write)?;
 
-    let mut index: usize = 0;
-    loop {
-        let slice = &mut in_buf[index..index + 1];
-        if index < IN_SIZE {
-            index += 1;
-        }
-        port.read_exact(slice)?;
-        if slice[0] == ZERO {
-            println!("-- cobs package received --");
-            break;
-        }
+    // typed access, the servant declares 0x13 as `Value::F32` in [0, 1]
+    master.set(0b001, 0x13, 0.25f32)?;
+    let value = master.get::<f32>(0b001, 0x13, 0)?;
+    println!("get 0x13 {}", value);
+    match master.get::<u32>(0b001, 0x13, 0) {
+        Err(Error::TypeMismatch(value)) => println!("get::<u32> 0x13, type mismatch {:?}", value),
+        other => println!("get::<u32> 0x13 {:?}", other),
     }
-    println!("cobs index {}", index);
-    Ok(deserialize_crc_cobs(in_buf).unwrap())
+    // rejected by the servant, `Error::Servant` with `ErrorCode::OutOfRange`
+    if let Err(err) = master.set(0b001, 0x13, 2.0f32) {
+        println!("set 0x13 2.0, {}", err);
+    }
+    Ok(())
 }
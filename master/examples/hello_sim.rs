@@ -0,0 +1,55 @@
+//! hello_sim.rs
+//!
+//! Protocol handshake with three simulated servants: a capable one, one built without
+//! batches and bulk transfer (the master downgrades), and one of another protocol
+//! version (the master refuses).
+//!
+//! On host `cd master` run:
+//! cargo run --example hello_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master};
+use master_and_servant::{Capabilities, Op, PROTOCOL, SCHEMA};
+use std::time::Duration;
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const DEVS: [u32; 3] = [0b001, 0b010, 0b100];
+const ID: u32 = 0x12;
+
+fn main() -> Result<(), Error> {
+    let servants = vec![
+        SimServant::new(DEVS[0]),
+        SimServant::new(DEVS[1]).with_capabilities(Capabilities::TELEMETRY),
+        SimServant::new(DEVS[2]).with_protocol(PROTOCOL + 1),
+    ];
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    println!("master protocol {} schema {:#010x}", PROTOCOL, SCHEMA);
+
+    for dev in DEVS {
+        match master.hello(dev) {
+            Ok(hello) => println!(
+                "dev {}: protocol {}, frames up to {} bytes, capabilities: {}",
+                dev, hello.protocol, hello.max_frame, hello.capabilities
+            ),
+            Err(err) => println!("dev {}: {}", dev, err),
+        }
+    }
+
+    let ops = [Op::Set(ID, 7u32.into()), Op::Get(ID, 0), Op::Get(ID, 1)];
+    for (i, dev) in DEVS.into_iter().take(2).enumerate() {
+        let before = master.port().servants[i].executed;
+        let outcomes = master.batch(dev, &ops)?;
+        let requests = master.port().servants[i].executed - before;
+        println!(
+            "dev {}: batch in {} request(s): {:?}",
+            dev, requests, outcomes
+        );
+    }
+
+    let mut blob = Vec::new();
+    if let Err(err) = master.read_blob(DEVS[1], 0, &mut blob) {
+        println!("dev {}: read blob: {}", DEVS[1], err);
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! link_sim.rs
+//!
+//! Link monitoring of two simulated servants: 0b001 is kept alive by the master,
+//! 0b010 sends heartbeats on its own. 0b001 is powered off for a while and comes
+//! back with a new session.
+//!
+//! On host `cd master` run:
+//! cargo run --example link_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Keepalive, Master, Retry};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const POLLED: u32 = 0b001;
+const PUSHING: u32 = 0b010;
+
+fn main() -> Result<(), Error> {
+    let servants = vec![
+        SimServant::new(POLLED),
+        SimServant::new(PUSHING).with_heartbeat(Duration::from_millis(200)),
+    ];
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(50),
+        retries: 1,
+        backoff: 1,
+    });
+    master.set_keepalive(Keepalive {
+        interval: Duration::from_millis(250),
+        degraded: 1,
+        down: 3,
+    });
+    let start = Instant::now();
+    master.on_link(move |event| println!("{:>5} ms {:?}", start.elapsed().as_millis(), event));
+    master.watch(POLLED);
+    master.watch(PUSHING);
+
+    for step in 0..30 {
+        match step {
+            8 => {
+                println!(
+                    "{:>5} ms power off {:#05b}",
+                    start.elapsed().as_millis(),
+                    POLLED
+                );
+                master.port().servants[0].power_off();
+            }
+            20 => {
+                println!(
+                    "{:>5} ms power on {:#05b}",
+                    start.elapsed().as_millis(),
+                    POLLED
+                );
+                master.port().servants[0].power_on();
+            }
+            _ => {}
+        }
+        master.keepalive()?;
+        master.listen(Duration::from_millis(100))?;
+    }
+
+    for dev in [POLLED, PUSHING] {
+        println!(
+            "{:#05b} {:?} {:?}",
+            dev,
+            master.link_state(dev),
+            master.session(dev)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! message_sim.rs
+//!
+//! Messages larger than a frame, sent to two simulated servants (echoing them) with
+//! different MTUs, over a lossy link. An incomplete message is discarded by the servant
+//! after its timeout.
+//!
+//! On host `cd master` run:
+//! cargo run --example message_sim
+//!
+use master::sim::{SimPort, SimServant, SIM_MESSAGE, SIM_MESSAGE_TIMEOUT};
+use master::{Error, Master};
+use master_and_servant::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const LOSS: f64 = 0.05;
+const DEVS: [u32; 2] = [1, 2];
+
+fn main() -> Result<(), Error> {
+    let servants = vec![
+        SimServant::new(DEVS[0]),
+        SimServant::new(DEVS[1]).with_mtu(8),
+    ];
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants).with_loss(LOSS));
+
+    let message: Vec<u8> = (0..400u32).map(|i| (i % 251) as u8).collect();
+    let mut reply = vec![0; SIM_MESSAGE];
+    for dev in DEVS {
+        let start = Instant::now();
+        let echo = master.send_message(dev, &message, &mut reply)?;
+        let mtu = master.mtu(dev).unwrap();
+        println!(
+            "dev {}: {} byte fragments, {} bytes in {:.0} ms, echo matches: {}",
+            dev,
+            mtu.fragment,
+            message.len(),
+            start.elapsed().as_secs_f32() * 1e3,
+            echo == message
+        );
+    }
+
+    let large = vec![0; SIM_MESSAGE + 1];
+    if let Err(err) = master.send_message(DEVS[0], &large, &mut reply) {
+        println!("send {} bytes: {}", large.len(), err);
+    }
+
+    // the first of two fragments, then nothing until the servant gave up
+    let fragment = |index| Command::Fragment {
+        msg: 42,
+        index,
+        count: 2,
+        data: [1, 2, 3].into_iter().collect(),
+        dev: DEVS[0],
+    };
+    master.send_to(DEVS[0], fragment(0))?;
+    sleep(SIM_MESSAGE_TIMEOUT + Duration::from_millis(100));
+    if let Err(err) = master.send_to(DEVS[0], fragment(1)) {
+        println!("fragment after timeout: {}", err);
+    }
+    Ok(())
+}
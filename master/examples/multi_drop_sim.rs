@@ -0,0 +1,59 @@
+//! multi_drop_sim.rs
+//!
+//! Addressing several simulated servants on a shared link, multicasting, broadcasting
+//! and batching.
+//!
+//! On host `cd master` run:
+//! cargo run --example multi_drop_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master};
+use master_and_servant::{Command, Op, Value};
+use std::time::Duration;
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const DEVS: [u32; 3] = [0b001, 0b010, 0b100];
+
+fn main() -> Result<(), Error> {
+    let servants = DEVS.iter().map(|dev| SimServant::new(*dev)).collect();
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+
+    for dev in DEVS {
+        let response = master.send_to(dev, Command::Set(0x12, Value::U32(dev), 0))?;
+        println!("send_to {:#05b}, response {:?}", dev, response);
+    }
+
+    // executed by all servants, without reply
+    master.broadcast(Command::Set(0x13, Value::U32(42), 0))?;
+
+    for dev in DEVS {
+        for id in [0x12, 0x13] {
+            let response = master.send_to(dev, Command::Get(id, 0, 0))?;
+            println!("send_to {:#05b}, response {:?}", dev, response);
+        }
+    }
+
+    // 0b1000 is not on the bus
+    let multicast = master.multicast(0b1111, Command::Get(0x12, 0, 0))?;
+    for (dev, response) in multicast.responses {
+        println!("multicast {:#06b}, response {:?}", dev, response);
+    }
+    println!("multicast, silent {:?}", multicast.silent);
+
+    // several operations in a single frame, split into batches of `BATCH`
+    let ops: Vec<Op> = (0..6)
+        .map(|i| match i % 2 {
+            0 => Op::Set(0x20 + i, Value::U32(i)),
+            _ => Op::Get(0x20 + i - 1, 0),
+        })
+        .collect();
+    for outcome in master.batch(0b001, &ops)? {
+        println!("batch, outcome {:?}", outcome);
+    }
+
+    for servant in &master.port().servants {
+        println!("executed {}", servant.executed);
+    }
+    Ok(())
+}
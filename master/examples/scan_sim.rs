@@ -0,0 +1,44 @@
+//! scan_sim.rs
+//!
+//! Discovering simulated servants, two of them sharing a `DevId`.
+//!
+//! On host `cd master` run:
+//! cargo run --example scan_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Discovered, Error, Master, Retry};
+use master_and_servant::{DevId, Version};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+
+fn main() -> Result<(), Error> {
+    let version = Version {
+        major: 0,
+        minor: 1,
+        patch: 0,
+    };
+    let servants = [0b0001, 0b0100, 0b0100, 0b1000]
+        .into_iter()
+        .map(|dev| SimServant::new(dev).with_version(version))
+        .collect();
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    // silent `DevId`s cost `1 + retries` timeouts
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(100),
+        retries: 1,
+        backoff: 1,
+    });
+
+    let start = Instant::now();
+    let found = master.scan((0..8).map(|bit| 1 << bit as DevId))?;
+    println!("scanned 8 DevIds in {:?}", start.elapsed());
+    for discovered in found {
+        match discovered {
+            Discovered::Servant(dev, info) => println!("found {:#06b}, {:?}", dev, info),
+            Discovered::Collision(dev) => println!("collision {:#06b}", dev),
+        }
+    }
+    Ok(())
+}
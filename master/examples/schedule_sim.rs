@@ -0,0 +1,67 @@
+//! schedule_sim.rs
+//!
+//! Coordinated writes to two simulated servants: values scheduled for the same master
+//! time, and staged values latched by a broadcast trigger. The servants log the
+//! (synchronised) time they applied each value at.
+//!
+//! On host `cd master` run:
+//! cargo run --example schedule_sim
+//!
+use master::sim::{SimPort, SimServant, SIM_SCHEDULE};
+use master::{Error, Master};
+use std::time::Duration;
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const DEVS: [u32; 2] = [0b001, 0b010];
+const ID: u32 = 0x20;
+
+fn main() -> Result<(), Error> {
+    let servants = vec![
+        SimServant::new(DEVS[0]).with_uptime(Duration::from_secs(5)),
+        SimServant::new(DEVS[1]).with_uptime(Duration::from_secs(42)),
+    ];
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    for dev in DEVS {
+        master.sync_time(dev)?;
+    }
+
+    let at = master.now() + 300_000;
+    println!("set at      {:>9.3} ms", at as f64 / 1e3);
+    for dev in DEVS {
+        master.set_at(dev, ID, 1u32, at)?;
+    }
+    master.listen(Duration::from_millis(500))?;
+
+    for dev in DEVS {
+        master.stage(dev, ID, 2u32)?;
+    }
+    println!("trigger at  {:>9.3} ms", master.now() as f64 / 1e3);
+    master.trigger()?;
+    master.listen(Duration::from_millis(100))?;
+
+    for servant in &master.port().servants {
+        for (time, id, value) in &servant.applied {
+            println!(
+                "{:#05b} {:#x} {:?} applied at {:>9.3} ms",
+                servant.dev(),
+                id,
+                value,
+                *time as f64 / 1e3
+            );
+        }
+    }
+
+    let passed = master.now() - 1_000;
+    if let Err(err) = master.set_at(DEVS[0], ID, 3u32, passed) {
+        println!("set at {:.3} ms: {}", passed as f64 / 1e3, err);
+    }
+    let later = master.now() + 10_000_000;
+    for i in 0..=SIM_SCHEDULE as u32 {
+        if let Err(err) = master.set_at(DEVS[0], ID, i, later) {
+            println!("set at {:.3} ms (#{}): {}", later as f64 / 1e3, i, err);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+//! signed_update_sim.rs
+//!
+//! Firmware update of a simulated servant checking image signatures: a signed image is
+//! committed, while unsigned, tampered, foreign and older images are rejected.
+//!
+//! On host `cd master` run:
+//! cargo run --example signed_update_sim
+//!
+use master::sign;
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master};
+use master_and_servant::Version;
+use std::time::Duration;
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const DEV: u32 = 1;
+const INSTALLED: Version = Version {
+    major: 1,
+    minor: 0,
+    patch: 0,
+};
+
+fn main() -> Result<(), Error> {
+    let key = sign::generate();
+    let servant = SimServant::new(DEV)
+        .with_version(INSTALLED)
+        .with_update_key(sign::public_key(&key));
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, vec![servant]));
+
+    let firmware: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    let newer = Version {
+        minor: 1,
+        ..INSTALLED
+    };
+    let older = Version {
+        major: 0,
+        minor: 9,
+        ..INSTALLED
+    };
+    let signed = sign::sign(&firmware, newer, &key);
+    // a flipped bit, as if altered after signing
+    let mut tampered = signed.clone();
+    tampered[100] ^= 1;
+
+    let images = [
+        ("signed 1.1.0", signed),
+        ("unsigned", firmware.clone()),
+        ("tampered", tampered),
+        ("other key", sign::sign(&firmware, newer, &sign::generate())),
+        ("signed 0.9.0", sign::sign(&firmware, older, &key)),
+    ];
+    for (name, image) in images {
+        match master.update(DEV, &image, |_, _| {}) {
+            Ok(()) => {
+                let same = master.port().servants[0].firmware() == Some(&image[..]);
+                println!("{:<13} committed, firmware matches: {}", name, same);
+            }
+            Err(err) => println!("{:<13} {}", name, err),
+        }
+    }
+    Ok(())
+}
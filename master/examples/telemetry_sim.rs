@@ -0,0 +1,49 @@
+//! telemetry_sim.rs
+//!
+//! Periodic and on-change notifications from a simulated servant.
+//!
+//! On host `cd master` run:
+//! cargo run --example telemetry_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master};
+use master_and_servant::{Access, Slot, Value};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const DEV: u32 = 0b001;
+
+fn main() -> Result<(), Error> {
+    // a temperature sensor
+    let sensor = Slot::new(0x13, 0, Access::ReadOnly, Value::F32(20.0));
+    let mut servant = SimServant::new(DEV).with_parameter(sensor);
+    servant.store(0x12, 0, Value::U32(7));
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, vec![servant]));
+    let start = Instant::now();
+    // or `master.notifications()` for a channel
+    master.on_notify(move |n| {
+        println!(
+            "{:>5} ms {:#05b} {:#x}/{} {:?}",
+            start.elapsed().as_millis(),
+            n.dev,
+            n.id,
+            n.parameter,
+            n.value
+        )
+    });
+
+    // every 250 ms
+    master.subscribe(DEV, 0x12, 0, Duration::from_millis(250), f32::INFINITY)?;
+    // on change by more than 1.0
+    master.subscribe(DEV, 0x13, 0, Duration::ZERO, 1.0)?;
+
+    for temperature in [20.5, 21.5, 21.8, 19.0] {
+        master.port().servants[0].store(0x13, 0, Value::F32(temperature));
+        master.listen(Duration::from_millis(300))?;
+    }
+    master.unsubscribe(DEV, 0x12, 0)?;
+    master.listen(Duration::from_millis(500))?;
+
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! time_sync_sim.rs
+//!
+//! Synchronises the clocks of two simulated servants, booted 5 s and 42 s ago, to the
+//! master clock. Notification timestamps are in the servant time base until synchronised,
+//! in the master time base after.
+//!
+//! On host `cd master` run:
+//! cargo run --example time_sync_sim
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master, Notification};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const DEVS: [u32; 2] = [0b001, 0b010];
+
+fn main() -> Result<(), Error> {
+    let servants = vec![
+        SimServant::new(DEVS[0]).with_uptime(Duration::from_secs(5)),
+        SimServant::new(DEVS[1]).with_uptime(Duration::from_secs(42)),
+    ];
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    let notifications = master.notifications();
+    for dev in DEVS {
+        master.subscribe(dev, 0x12, 0, Duration::from_millis(250), f32::INFINITY)?;
+    }
+
+    println!("-- unsynchronised --");
+    master.listen(Duration::from_millis(600))?;
+    report(&master, &notifications);
+
+    for dev in DEVS {
+        let sync = master.sync_time(dev)?;
+        println!(
+            "{:#05b} offset {:.3} s, round trip {:?}",
+            dev,
+            sync.offset as f64 / 1e6,
+            sync.delay
+        );
+    }
+
+    // drop those received while synchronising
+    notifications.try_iter().count();
+    println!("-- synchronised --");
+    master.listen(Duration::from_millis(600))?;
+    report(&master, &notifications);
+
+    Ok(())
+}
+
+fn report<P: master::Port>(master: &Master<P>, notifications: &Receiver<Notification>) {
+    for n in notifications.try_iter() {
+        println!(
+            "{:#05b} sampled at {:>9.3} ms",
+            n.dev,
+            n.timestamp as f64 / 1e3
+        );
+    }
+    println!("master now  {:>9.3} ms", master.now() as f64 / 1e3);
+}
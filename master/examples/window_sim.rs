@@ -0,0 +1,53 @@
+//! window_sim.rs
+//!
+//! Throughput of stop-and-wait vs. windowed requests against a simulated servant.
+//!
+//! On host `cd master` run:
+//! cargo run --example window_sim --release
+//!
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master, Retry};
+use master_and_servant::{Command, Value};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const LOSS: [f64; 2] = [0.0, 0.02];
+const N: u32 = 50;
+
+fn main() -> Result<(), Error> {
+    let cmds: Vec<Command> = (0..N)
+        .map(|i| match i % 2 {
+            0 => Command::Set(i, Value::U32(i), 0b001),
+            _ => Command::Get(i - 1, 0, 0b001),
+        })
+        .collect();
+
+    for (loss, window) in LOSS.iter().flat_map(|l| [1, 2, 4, 8].map(|w| (*l, w))) {
+        let port = SimPort::new(BAUD, TURNAROUND, vec![SimServant::new(0b001)]).with_loss(loss);
+        let mut master = Master::new(port);
+        master.set_baud(BAUD);
+        master.set_retry(Retry {
+            timeout: Duration::from_millis(100),
+            ..Retry::default()
+        });
+
+        let start = Instant::now();
+        let responses = master.pipeline(&cmds, window)?;
+        let elapsed = start.elapsed();
+
+        let failed = responses.iter().filter(|r| r.is_err()).count();
+        let executed = master.port().servants[0].executed;
+        println!(
+            "loss {}, window {}: {} requests in {:?}, {:.1} requests/s, {} failed, {} executed",
+            loss,
+            window,
+            cmds.len(),
+            elapsed,
+            cmds.len() as f64 / elapsed.as_secs_f64(),
+            failed,
+            executed
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Bulk transfer
+//!
+//! `read_blob` and `write_blob` stream a blob of a servant (see `Blobs`) to a
+//! `std::io::Write` or from a `std::io::Read`, in `BLOB_CHUNK`s, and compare the crc
+//! of the data transferred to the one computed by the servant.
+
+use crate::{Error, Master, Port};
+use master_and_servant::{BlobId, Capabilities, Command, DevId, Response, BLOB_CHUNK, CKSUM};
+use std::io::{Read, Write};
+
+impl<P: Port> Master<P> {
+    /// Read `blob` of `dev` into `out`, returns its size
+    pub fn read_blob(
+        &mut self,
+        dev: DevId,
+        blob: BlobId,
+        mut out: impl Write,
+    ) -> Result<u32, Error> {
+        self.require(dev, Capabilities::BULK)?;
+        let size = self.open_blob(dev, blob, None)?;
+        let mut digest = CKSUM.digest();
+        let mut offset = 0;
+        while offset < size {
+            match self.send_to(dev, Command::BlobRead { offset, dev })? {
+                Response::BlobData(data) if !data.is_empty() => {
+                    out.write_all(&data)?;
+                    digest.update(&data);
+                    offset += data.len() as u32;
+                }
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        self.close_blob(dev, digest.finalize())?;
+        Ok(size)
+    }
+
+    /// Write `size` bytes read from `input` to `blob` of `dev`
+    ///
+    /// The servant writes the blob in place as the chunks arrive, the checksum is only
+    /// compared at the end: on `Error::Checksum` (or any failure) the blob is partly
+    /// overwritten, and left open without `BlobProvider::close` being called. Repeat the
+    /// write to complete it.
+    pub fn write_blob(
+        &mut self,
+        dev: DevId,
+        blob: BlobId,
+        size: u32,
+        mut input: impl Read,
+    ) -> Result<(), Error> {
+        self.require(dev, Capabilities::BULK)?;
+        self.open_blob(dev, blob, Some(size))?;
+        let mut digest = CKSUM.digest();
+        let mut buf = [0; BLOB_CHUNK];
+        let mut offset = 0;
+        while offset < size {
+            let buf = &mut buf[..(size - offset).min(BLOB_CHUNK as u32) as usize];
+            input.read_exact(buf)?;
+            digest.update(buf);
+            let cmd = Command::BlobWrite {
+                offset,
+                data: buf.iter().copied().collect(),
+                dev,
+            };
+            match self.send_to(dev, cmd)? {
+                Response::SetOk => offset += buf.len() as u32,
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        self.close_blob(dev, digest.finalize())
+    }
+
+    fn open_blob(&mut self, dev: DevId, blob: BlobId, size: Option<u32>) -> Result<u32, Error> {
+        match self.send_to(dev, Command::BlobOpen { blob, size, dev })? {
+            Response::BlobOpened { size: opened } if size.unwrap_or(opened) == opened => Ok(opened),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Compare the servant's checksum of the open blob to `crc` and close it if they agree
+    fn close_blob(&mut self, dev: DevId, crc: u32) -> Result<(), Error> {
+        match self.send_to(dev, Command::BlobChecksum(dev))? {
+            Response::BlobChecksum(servant) if servant == crc => {}
+            Response::BlobChecksum(servant) => {
+                return Err(Error::Checksum {
+                    local: crc,
+                    servant,
+                })
+            }
+            resp => return Err(Error::Unexpected(resp)),
+        }
+        match self.send_to(dev, Command::BlobClose(dev))? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Servant discovery and address assignment
+//!
+//! `scan` probes `DevId`s by `Command::Ping`. `assign` hands out `DevId`s to unassigned
+//! servants, found by binary search over their unique hardware ids (`Uid`): the servants
+//! matching a prefix claim (`Command::Claim`), on collision the prefix is extended by one
+//! bit, a single claim is answered by `Command::Assign`.
+
+use crate::request::Probe;
+use crate::{Error, Master, Port};
+use master_and_servant::{Command, DevId, Info, Response, Uid, BROADCAST, UNASSIGNED};
+
+/// A servant found by `Master::scan`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Discovered {
+    /// A single servant answered
+    Servant(DevId, Info),
+    /// Several servants answered, their replies collided
+    Collision(DevId),
+}
+
+impl<P: Port> Master<P> {
+    /// Probe each `DevId` in `devs` by `Command::Ping`, returns the servants found
+    ///
+    /// Servants sharing a `DevId` reply simultaneously, the garbled frames (or a second
+    /// reply) are reported as a collision. Silent `DevId`s are left out.
+    pub fn scan(
+        &mut self,
+        devs: impl IntoIterator<Item = DevId>,
+    ) -> Result<Vec<Discovered>, Error> {
+        let mut found = Vec::new();
+        for dev in devs {
+            if dev == BROADCAST || dev == UNASSIGNED {
+                continue;
+            }
+            match self.probe(Command::Ping(dev))? {
+                Probe::Single(Response::Pong(info)) => found.push(Discovered::Servant(dev, info)),
+                Probe::Collision => found.push(Discovered::Collision(dev)),
+                _ => {}
+            }
+        }
+        Ok(found)
+    }
+
+    /// Assign `DevId`s from `pool` to the unassigned servants, returns the assignments
+    ///
+    /// The `DevId`s in `pool` should not be in use (see `scan`). Assignment stops when
+    /// `pool` is exhausted, servants sharing a `Uid` cannot be told apart and are left
+    /// unassigned. A servant not acknowledging its `Command::Assign` (timeout or error)
+    /// is skipped and the pass goes on. It may have adopted the `DevId` all the same, so
+    /// that `DevId` is not handed out again; a later `scan` tells whether it is in use,
+    /// and a servant still unassigned takes part in the next pass.
+    pub fn assign(
+        &mut self,
+        pool: impl IntoIterator<Item = DevId>,
+    ) -> Result<Vec<(Uid, DevId)>, Error> {
+        let mut pool = pool
+            .into_iter()
+            .filter(|dev| *dev != UNASSIGNED && *dev != BROADCAST);
+        let mut assigned = Vec::new();
+        // depth first, the prefixes still to be searched, and whether to probe them
+        let mut prefixes = vec![(Uid::from_u128(0), 0, true)];
+        while let Some((prefix, len, probe)) = prefixes.pop() {
+            let claimed = match probe {
+                true => self.probe(Command::Claim(prefix, len, UNASSIGNED))?,
+                false => Probe::Collision,
+            };
+            match claimed {
+                Probe::Single(Response::Claim(uid)) => {
+                    let Some(dev) = pool.next() else {
+                        break;
+                    };
+                    match self.request(Command::Assign(uid, dev, UNASSIGNED)) {
+                        Ok(Response::SetOk) => assigned.push((uid, dev)),
+                        Ok(_) | Err(Error::Timeout) | Err(Error::Servant { .. }) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Probe::Silent => {
+                    // the `1` sibling of a silent `0` half holds the collision of the parent
+                    if let Some(sibling) = prefixes.last_mut() {
+                        if len > 0 && sibling.0 == with_bit(prefix, len - 1) && sibling.0 != prefix
+                        {
+                            sibling.2 = false;
+                        }
+                    }
+                }
+                Probe::Collision if len < Uid::BITS => {
+                    prefixes.push((with_bit(prefix, len), len + 1, true));
+                    prefixes.push((prefix, len + 1, true));
+                }
+                _ => {}
+            }
+        }
+        Ok(assigned)
+    }
+}
+
+/// `uid` with bit `n` (counted from the most significant) set
+fn with_bit(uid: Uid, n: u8) -> Uid {
+    Uid::from_u128(uid.to_u128() | 1 << (Uid::BITS - 1 - n))
+}
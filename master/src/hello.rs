@@ -0,0 +1,59 @@
+//! Protocol handshake
+//!
+//! `hello` exchanges `Hello` with a servant: the protocol version, the schema hash of
+//! the encoding, the largest frame received and the capabilities. A servant of another
+//! protocol version or schema cannot be talked to and is refused. A compatible servant
+//! lacking capabilities is downgraded to: `batch` falls back to single requests, while
+//! subscriptions, blobs and messages fail with `Error::Unsupported`. Servants never
+//! greeted are assumed to be capable of everything.
+
+use crate::{Error, Master, Port};
+use master_and_servant::{Capabilities, Command, DevId, Hello, Reply, Request, Response, WireSize};
+
+/// Capabilities of the master
+pub const MASTER_CAPABILITIES: Capabilities = Capabilities::MULTICAST
+    .union(Capabilities::BATCH)
+    .union(Capabilities::TELEMETRY)
+    .union(Capabilities::BULK)
+    .union(Capabilities::AUTH);
+
+impl<P: Port> Master<P> {
+    /// Exchange `Hello` with `dev`, returns its parameters
+    ///
+    /// Fails with `Error::Incompatible` if the servant speaks another protocol.
+    pub fn hello(&mut self, dev: DevId) -> Result<Hello, Error> {
+        let ours = Hello::new(Reply::MAX_FRAME_SIZE as u16, MASTER_CAPABILITIES);
+        let theirs = match self.send_to(dev, Command::Hello(ours, dev))? {
+            Response::Hello(theirs) => theirs,
+            resp => return Err(Error::Unexpected(resp)),
+        };
+        if !ours.compatible(&theirs) || (theirs.max_frame as usize) < Request::MAX_FRAME_SIZE {
+            self.peers.remove(&dev);
+            return Err(Error::Incompatible(theirs));
+        }
+        self.peers.insert(dev, theirs);
+        Ok(theirs)
+    }
+
+    /// The parameters of `dev` from the last `hello`, if any
+    pub fn peer(&self, dev: DevId) -> Option<Hello> {
+        self.peers.get(&dev).copied()
+    }
+
+    /// Whether `dev` has `capabilities`, assumed until greeted
+    pub fn supports(&self, dev: DevId, capabilities: Capabilities) -> bool {
+        self.peers
+            .get(&dev)
+            .is_none_or(|peer| peer.capabilities.contains(capabilities))
+    }
+
+    /// `Error::Unsupported` unless `dev` has `capabilities`
+    pub(crate) fn require(&self, dev: DevId, capabilities: Capabilities) -> Result<(), Error> {
+        match self.peers.get(&dev) {
+            Some(peer) if !peer.capabilities.contains(capabilities) => Err(Error::Unsupported(
+                capabilities.intersection(Capabilities(!peer.capabilities.0)),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Firmware images
+//!
+//! An image is read from a raw binary, or from the loadable segments of a 32-bit
+//! little-endian ELF file (as built for the servant), laid out by load address with the
+//! gaps filled as erased flash.
+//!
+//! The load address is the physical one (`p_paddr`): initialised data runs from RAM but
+//! is loaded from its copy in flash. Segments loaded outside `FLASH` are refused.
+
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const PT_LOAD: u32 = 1;
+
+/// Flash of the servant (`servant/memory.x`)
+pub const FLASH: Range<u32> = 0x0040_0000..0x0060_0000;
+
+/// A firmware image, to be written at `base`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// Load address, 0 for a raw binary
+    pub base: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Read an ELF file or raw binary
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::read(path)?;
+        if file.starts_with(ELF_MAGIC) {
+            Self::from_elf(&file)
+        } else {
+            Ok(Self {
+                base: 0,
+                data: file,
+            })
+        }
+    }
+
+    /// The loadable segments of a 32-bit little-endian ELF file
+    pub fn from_elf(elf: &[u8]) -> io::Result<Self> {
+        // EI_CLASS 1: 32-bit, EI_DATA 1: little-endian
+        if !elf.starts_with(ELF_MAGIC) || elf.get(4..6) != Some(&[1, 1]) {
+            return Err(invalid("not a 32-bit little-endian ELF file"));
+        }
+        let phoff = u32_at(elf, 0x1c)? as usize;
+        let phentsize = u16_at(elf, 0x2a)? as usize;
+        let phnum = u16_at(elf, 0x2c)? as usize;
+
+        // (load address, file contents) of each loadable segment
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            let (offset, paddr, filesz) = (
+                u32_at(elf, ph + 4)?,
+                u32_at(elf, ph + 12)?,
+                u32_at(elf, ph + 16)?,
+            );
+            if u32_at(elf, ph)? != PT_LOAD || filesz == 0 {
+                continue;
+            }
+            let in_flash = paddr
+                .checked_add(filesz)
+                .is_some_and(|end| FLASH.start <= paddr && end <= FLASH.end);
+            if !in_flash {
+                return Err(invalid("segment loaded outside flash"));
+            }
+            let start = offset as usize;
+            let data = elf
+                .get(start..start + filesz as usize)
+                .ok_or_else(|| invalid("segment beyond end of file"))?;
+            segments.push((paddr, data));
+        }
+
+        let base = segments
+            .iter()
+            .map(|(paddr, _)| *paddr)
+            .min()
+            .ok_or_else(|| invalid("no loadable segment"))?;
+        let end = segments
+            .iter()
+            .map(|(paddr, data)| (paddr - base) as usize + data.len())
+            .max()
+            .unwrap_or(0);
+        let mut data = vec![0xff; end];
+        for (paddr, segment) in segments {
+            let start = (paddr - base) as usize;
+            data[start..start + segment.len()].copy_from_slice(segment);
+        }
+        Ok(Self { base, data })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn u16_at(elf: &[u8], at: usize) -> io::Result<u16> {
+    elf.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("truncated ELF header"))
+}
+
+fn u32_at(elf: &[u8], at: usize) -> io::Result<u32> {
+    elf.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("truncated ELF header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 32-bit little-endian ELF file with program headers
+    /// `(type, vaddr, paddr, contents)`, the contents following the headers
+    fn elf(segments: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+        const EHSIZE: usize = 52;
+        const PHENTSIZE: usize = 32;
+        let mut elf = vec![0; EHSIZE];
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4..6].copy_from_slice(&[1, 1]);
+        elf[0x1c..0x20].copy_from_slice(&(EHSIZE as u32).to_le_bytes());
+        elf[0x2a..0x2c].copy_from_slice(&(PHENTSIZE as u16).to_le_bytes());
+        elf[0x2c..0x2e].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        let mut offset = EHSIZE + segments.len() * PHENTSIZE;
+        for (kind, vaddr, paddr, data) in segments {
+            let filesz = data.len() as u32;
+            for field in [*kind, offset as u32, *vaddr, *paddr, filesz, filesz, 0, 4] {
+                elf.extend(field.to_le_bytes());
+            }
+            offset += data.len();
+        }
+        for (.., data) in segments {
+            elf.extend(*data);
+        }
+        elf
+    }
+
+    #[test]
+    fn segments_at_load_address() {
+        let elf = elf(&[
+            (PT_LOAD, 0x40_0000, 0x40_0000, &[1, 2, 3, 4]),
+            // initialised data, run from RAM
+            (PT_LOAD, 0x2040_0000, 0x40_0008, &[5, 6]),
+            // bss
+            (PT_LOAD, 0x2040_0002, 0x2040_0002, &[]),
+            // not loaded
+            (4, 0, 0, &[7]),
+        ]);
+        let image = Image::from_elf(&elf).unwrap();
+        assert_eq!(image.base, 0x40_0000);
+        assert_eq!(image.data, [1, 2, 3, 4, 0xff, 0xff, 0xff, 0xff, 5, 6]);
+    }
+
+    #[test]
+    fn segment_outside_flash() {
+        let elf = elf(&[
+            (PT_LOAD, 0x40_0000, 0x40_0000, &[1, 2, 3, 4]),
+            (PT_LOAD, 0x2040_0000, 0x2040_0000, &[5, 6]),
+        ]);
+        let err = Image::from_elf(&elf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn not_elf32_le() {
+        let mut elf = elf(&[(PT_LOAD, 0x40_0000, 0x40_0000, &[1])]);
+        elf[4] = 2;
+        assert!(Image::from_elf(&elf).is_err());
+    }
+}
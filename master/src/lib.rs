@@ -1,7 +1,29 @@
+use master_and_servant::{
+    Capabilities, ErrorCode, FrameError, Hello, Response, Value, PROTOCOL, SCHEMA,
+};
 use serial2::SerialPort;
-use std::io::Result;
+use std::fmt;
+use std::io::{Read, Write};
 use std::time::Duration;
 
+mod blob;
+mod discover;
+mod hello;
+pub mod image;
+mod link;
+mod message;
+mod request;
+pub mod sign;
+pub mod sim;
+mod time;
+mod update;
+pub use discover::Discovered;
+pub use hello::MASTER_CAPABILITIES;
+pub use link::{Keepalive, LinkEvent, LinkState};
+pub use request::{Master, Multicast, Notification, Retry};
+pub use time::{ClockSync, SYNC_ROUNDS};
+pub use update::RESUMES;
+
 // On Windows, use something like "COM1".
 // For COM ports above COM9, you need to use the win32 device namespace, for example "\\.\COM10" (or "\\\\.\\COM10" with string escaping).
 // For more details, see: https://learn.microsoft.com/en-us/windows/win32/fileio/naming-a-file?redirectedfrom=MSDN#win32-device-namespaces
@@ -14,8 +36,15 @@ static COM_PATH: &str = "COM3";
 // A one second timeout
 const TIME_OUT: Duration = Duration::from_millis(1000);
 
-pub fn open() -> Result<SerialPort> {
-    let mut port = SerialPort::open(COM_PATH, 9600)?;
+pub const BAUD: u32 = 9600;
+
+/// Time to transmit a byte at `baud` (8N1, 10 bits per byte)
+pub fn byte_time(baud: u32) -> Duration {
+    Duration::from_secs(10) / baud
+}
+
+pub fn open() -> std::io::Result<SerialPort> {
+    let mut port = SerialPort::open(COM_PATH, BAUD)?;
     // Needed for windows, but should not hurt on Linux
     port.set_dtr(true)?;
     port.set_rts(true)?;
@@ -24,3 +53,86 @@ pub fn open() -> Result<SerialPort> {
 
     Ok(port)
 }
+
+/// A port with a configurable read timeout
+pub trait Port: Read + Write {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()>;
+}
+
+impl Port for SerialPort {
+    fn set_read_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        SerialPort::set_read_timeout(self, timeout)
+    }
+}
+
+/// Errors raised by the master request API
+#[derive(Debug)]
+pub enum Error {
+    /// The serial port failed
+    Io(std::io::Error),
+    /// A frame could not be encoded or decoded
+    Frame(FrameError),
+    /// The servant failed to execute (or decode) the request, see `ErrorCode` for `detail`
+    Servant { code: ErrorCode, detail: u32 },
+    /// No reply, all retransmissions exhausted
+    Timeout,
+    /// The value read is not of the requested type
+    TypeMismatch(Value),
+    /// The response does not answer the request
+    Unexpected(Response),
+    /// Broadcasts are not acknowledged, use `Master::broadcast`
+    Broadcast,
+    /// The crc of the blob transferred differs from the one computed by the servant
+    Checksum { local: u32, servant: u32 },
+    /// A message of `size` bytes exceeds the `max` accepted by the servant, or the
+    /// reply exceeds the buffer
+    TooLarge { size: usize, max: usize },
+    /// The servant speaks another protocol, see `Master::hello`
+    Incompatible(Hello),
+    /// The servant lacks the capabilities
+    Unsupported(Capabilities),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Frame(err) => write!(f, "frame error: {}", err),
+            Error::Servant { code, detail } => write!(f, "servant error: {} ({})", code, detail),
+            Error::Timeout => f.write_str("request timed out"),
+            Error::TypeMismatch(value) => write!(f, "value of unexpected type: {:?}", value),
+            Error::Unexpected(resp) => write!(f, "unexpected response: {:?}", resp),
+            Error::Broadcast => f.write_str("broadcasts are not acknowledged"),
+            Error::Incompatible(hello) => write!(
+                f,
+                "incompatible servant, protocol {} schema {:#010x} (ours {} {:#010x})",
+                hello.protocol, hello.schema, PROTOCOL, SCHEMA
+            ),
+            Error::Unsupported(capabilities) => {
+                write!(f, "not supported by the servant: {}", capabilities)
+            }
+            Error::TooLarge { size, max } => {
+                write!(f, "message of {} bytes exceeds {} bytes", size, max)
+            }
+            Error::Checksum { local, servant } => write!(
+                f,
+                "blob checksum {:#010x} differs from the servant's {:#010x}",
+                local, servant
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<FrameError> for Error {
+    fn from(err: FrameError) -> Self {
+        Error::Frame(err)
+    }
+}
@@ -0,0 +1,324 @@
+//! Link health monitoring
+//!
+//! The master tracks the `LinkState` of the servants it `watch`es. Any frame received from
+//! a servant counts as a sign of life, `keepalive` sends `Command::Heartbeat` to the
+//! servants that have been silent for the keepalive interval. Unanswered heartbeats (and
+//! timed out requests) degrade the link, after `Keepalive::down` misses it is down.
+//!
+//! Servants may also push heartbeats on their own, then `keepalive` only probes servants
+//! that went silent. Each heartbeat carries the servant `Session`, a change of session
+//! means the servant was reset.
+
+use crate::{Error, Master, Port};
+use master_and_servant::{Command, DevId, Response, Session};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Health of the link to a servant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// The servant answered recently
+    Up,
+    /// Some heartbeats went unanswered
+    Degraded,
+    /// The servant is silent (or not heard of yet)
+    Down,
+}
+
+/// Keepalive policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// A servant silent for `interval` is sent a heartbeat
+    pub interval: Duration,
+    /// Consecutive misses after which the link is degraded
+    pub degraded: u32,
+    /// Consecutive misses after which the link is down
+    pub down: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            degraded: 1,
+            down: 3,
+        }
+    }
+}
+
+/// A change reported to the `Master::on_link` callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link state of `dev` changed
+    State {
+        dev: DevId,
+        from: LinkState,
+        to: LinkState,
+    },
+    /// `dev` reports a new session, it was reset
+    Reset {
+        dev: DevId,
+        from: Session,
+        to: Session,
+    },
+}
+
+struct Link {
+    state: LinkState,
+    session: Option<Session>,
+    /// Last frame received
+    seen: Option<Instant>,
+    /// Consecutive misses
+    missed: u32,
+}
+
+/// The watched servants
+pub(crate) struct Links {
+    keepalive: Keepalive,
+    links: BTreeMap<DevId, Link>,
+    callback: Option<Box<dyn FnMut(LinkEvent) + Send>>,
+}
+
+impl Links {
+    pub(crate) fn new() -> Self {
+        Self {
+            keepalive: Keepalive::default(),
+            links: BTreeMap::new(),
+            callback: None,
+        }
+    }
+
+    /// A frame was received from `dev`, carrying its `session` if a heartbeat
+    pub(crate) fn seen(&mut self, dev: DevId, session: Option<Session>) {
+        let Some(link) = self.links.get_mut(&dev) else {
+            return;
+        };
+        link.seen = Some(Instant::now());
+        link.missed = 0;
+        let from = link.state;
+        link.state = LinkState::Up;
+        let reset = match (link.session, session) {
+            (Some(from), Some(to)) if from != to => Some(LinkEvent::Reset { dev, from, to }),
+            _ => None,
+        };
+        if session.is_some() {
+            link.session = session;
+        }
+        if from != LinkState::Up {
+            self.emit(LinkEvent::State {
+                dev,
+                from,
+                to: LinkState::Up,
+            });
+        }
+        if let Some(event) = reset {
+            self.emit(event);
+        }
+    }
+
+    /// `dev` failed to answer
+    pub(crate) fn missed(&mut self, dev: DevId) {
+        let Some(link) = self.links.get_mut(&dev) else {
+            return;
+        };
+        link.missed = link.missed.saturating_add(1);
+        let from = link.state;
+        link.state = if link.missed >= self.keepalive.down {
+            LinkState::Down
+        } else if link.missed >= self.keepalive.degraded && from == LinkState::Up {
+            LinkState::Degraded
+        } else {
+            from
+        };
+        let to = link.state;
+        if from != to {
+            self.emit(LinkEvent::State { dev, from, to });
+        }
+    }
+
+    fn emit(&mut self, event: LinkEvent) {
+        if let Some(callback) = &mut self.callback {
+            callback(event);
+        }
+    }
+}
+
+impl<P: Port> Master<P> {
+    /// Monitor the link to `dev`, which is `Down` until it is heard of
+    pub fn watch(&mut self, dev: DevId) {
+        self.links.links.entry(dev).or_insert(Link {
+            state: LinkState::Down,
+            session: None,
+            seen: None,
+            missed: 0,
+        });
+    }
+
+    /// Stop monitoring the link to `dev`
+    pub fn unwatch(&mut self, dev: DevId) {
+        self.links.links.remove(&dev);
+    }
+
+    /// State of the link to `dev`, `None` if not watched
+    pub fn link_state(&self, dev: DevId) -> Option<LinkState> {
+        self.links.links.get(&dev).map(|link| link.state)
+    }
+
+    /// Last session reported by `dev`, if watched and heard of
+    pub fn session(&self, dev: DevId) -> Option<Session> {
+        self.links.links.get(&dev)?.session
+    }
+
+    /// Set the keepalive policy
+    pub fn set_keepalive(&mut self, keepalive: Keepalive) {
+        self.links.keepalive = keepalive;
+    }
+
+    /// Call `f` on each link state change and servant reset, replacing the previous callback
+    pub fn on_link(&mut self, f: impl FnMut(LinkEvent) + Send + 'static) {
+        self.links.callback = Some(Box::new(f));
+    }
+
+    /// Send `Command::Heartbeat` to `dev`, returns its session
+    pub fn heartbeat(&mut self, dev: DevId) -> Result<Session, Error> {
+        match self.send_to(dev, Command::Heartbeat(dev))? {
+            Response::Heartbeat(session) => Ok(session),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Send a heartbeat to each watched servant silent for the keepalive interval
+    ///
+    /// To be called regularly, at least once per interval. Heartbeats are retransmitted
+    /// (default retransmission policy), a servant that does not answer is counted a miss.
+    pub fn keepalive(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let interval = self.links.keepalive.interval;
+        let due: Vec<DevId> = self
+            .links
+            .links
+            .iter()
+            .filter(|(_, link)| link.seen.is_none_or(|seen| now - seen >= interval))
+            .map(|(dev, _)| *dev)
+            .collect();
+        for dev in due {
+            // a received reply is accounted for by `seen`, a timeout by `request_with`
+            match self.heartbeat(dev) {
+                Ok(_) | Err(Error::Timeout) | Err(Error::Servant { .. }) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    const DEV: DevId = 0b001;
+
+    /// Links watching `DEV`, and the events they report
+    fn links() -> (Links, Receiver<LinkEvent>) {
+        let mut links = Links::new();
+        links.links.insert(
+            DEV,
+            Link {
+                state: LinkState::Down,
+                session: None,
+                seen: None,
+                missed: 0,
+            },
+        );
+        let (tx, rx) = channel();
+        links.callback = Some(Box::new(move |event| tx.send(event).unwrap()));
+        (links, rx)
+    }
+
+    fn state(links: &Links) -> LinkState {
+        links.links[&DEV].state
+    }
+
+    fn session(id: u32) -> Session {
+        Session { boot: 1, id }
+    }
+
+    #[test]
+    fn missed_heartbeats_degrade_the_link() {
+        let (mut links, events) = links();
+        links.seen(DEV, None);
+        assert_eq!(state(&links), LinkState::Up);
+        let up = LinkEvent::State {
+            dev: DEV,
+            from: LinkState::Down,
+            to: LinkState::Up,
+        };
+        assert_eq!(events.try_recv(), Ok(up));
+
+        links.missed(DEV);
+        assert_eq!(state(&links), LinkState::Degraded);
+        links.missed(DEV);
+        assert_eq!(state(&links), LinkState::Degraded);
+        links.missed(DEV);
+        assert_eq!(state(&links), LinkState::Down);
+        let changes: Vec<LinkEvent> = events.try_iter().collect();
+        assert_eq!(
+            changes,
+            [
+                LinkEvent::State {
+                    dev: DEV,
+                    from: LinkState::Up,
+                    to: LinkState::Degraded,
+                },
+                LinkEvent::State {
+                    dev: DEV,
+                    from: LinkState::Degraded,
+                    to: LinkState::Down,
+                },
+            ]
+        );
+        // any frame restores the link
+        links.seen(DEV, None);
+        assert_eq!(state(&links), LinkState::Up);
+        links.missed(DEV);
+        assert_eq!(state(&links), LinkState::Degraded);
+    }
+
+    #[test]
+    fn down_is_not_degraded_by_misses() {
+        let (mut links, events) = links();
+        links.missed(DEV);
+        assert_eq!(state(&links), LinkState::Down);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn new_session_is_a_reset() {
+        let (mut links, events) = links();
+        links.seen(DEV, Some(session(1)));
+        // replies carry no session
+        links.seen(DEV, None);
+        links.seen(DEV, Some(session(1)));
+        assert!(matches!(events.try_recv(), Ok(LinkEvent::State { .. })));
+        assert!(events.try_recv().is_err());
+
+        links.seen(DEV, Some(session(2)));
+        let reset = LinkEvent::Reset {
+            dev: DEV,
+            from: session(1),
+            to: session(2),
+        };
+        assert_eq!(events.try_recv(), Ok(reset));
+        assert_eq!(links.links[&DEV].session, Some(session(2)));
+    }
+
+    #[test]
+    fn unwatched_devs_are_ignored() {
+        let (mut links, events) = links();
+        links.seen(0b010, Some(session(1)));
+        links.missed(0b010);
+        assert!(events.try_recv().is_err());
+        assert!(!links.links.contains_key(&0b010));
+    }
+}
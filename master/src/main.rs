@@ -0,0 +1,148 @@
+//! Command line tool for the servants
+//!
+//! On host `cd master` run, e.g.:
+//! cargo run -- update ../target/thumbv7em-none-eabihf/release/examples/cmd_crc_cobs_lib
+//!
+//! `--sim` uploads to a simulated servant instead of the serial port.
+//!
+//! Images are signed by, e.g. (`keygen` also writes the public key to `update.pub`):
+//! cargo run -- keygen update.key
+//! cargo run -- sign ../target/thumbv7em-none-eabihf/release/examples/cmd_crc_cobs_lib \
+//!     --key update.key --version 0.2.0 -o signed.bin
+//! cargo run -- update signed.bin --sim --key update.key
+use clap::{Parser, Subcommand};
+use master::image::Image;
+use master::sign;
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master, Port};
+use master_and_servant::{DevId, Version};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// simulated link, faster than the serial port to keep uploads short
+const SIM_BAUD: u32 = 115_200;
+const SIM_TURNAROUND: Duration = Duration::from_millis(1);
+
+#[derive(Parser)]
+#[command(about = "Command line tool for the servants")]
+struct Cli {
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Upload a firmware image (ELF or raw binary) to a servant
+    Update {
+        /// The image file
+        image: PathBuf,
+        /// The servant to update
+        #[arg(long, default_value_t = 1)]
+        dev: DevId,
+        /// Upload to a simulated servant
+        #[arg(long)]
+        sim: bool,
+        /// Have the simulated servant only accept images signed with this key
+        #[arg(long, requires = "sim")]
+        key: Option<PathBuf>,
+    },
+    /// Generate a signing key, writing the public key for the servant next to it (.pub)
+    Keygen {
+        /// The key file to write
+        key: PathBuf,
+    },
+    /// Sign a firmware image (ELF or raw binary), writing a raw binary
+    Sign {
+        /// The image file
+        image: PathBuf,
+        /// The signing key
+        #[arg(long)]
+        key: PathBuf,
+        /// Firmware version of the image, e.g., 1.2.3
+        #[arg(long, value_parser = parse_version)]
+        version: Version,
+        /// The signed image file to write
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Cli::parse().command {
+        Cmd::Update {
+            image,
+            dev,
+            sim,
+            key,
+        } => {
+            let image = Image::load(image)?;
+            println!(
+                "image of {} bytes at {:#010x}",
+                image.data.len(),
+                image.base
+            );
+            if sim {
+                let mut servant = SimServant::new(dev);
+                if let Some(key) = key {
+                    servant = servant.with_update_key(sign::public_key(&sign::load_key(key)?));
+                }
+                let mut master = Master::new(SimPort::new(SIM_BAUD, SIM_TURNAROUND, vec![servant]));
+                update(&mut master, dev, &image)?;
+                let same = master.port().servants[0].firmware() == Some(&image.data[..]);
+                println!("simulated servant firmware matches: {}", same);
+            } else {
+                let mut master = Master::new(master::open()?);
+                update(&mut master, dev, &image)?;
+            }
+        }
+        Cmd::Keygen { key } => {
+            let pair = sign::generate();
+            let public = key.with_extension("pub");
+            sign::save_key(&key, &pair)?;
+            sign::save_public_key(&public, &pair)?;
+            println!("public key written to {}", public.display());
+        }
+        Cmd::Sign {
+            image,
+            key,
+            version,
+            out,
+        } => {
+            let image = Image::load(image)?;
+            let signed = sign::sign(&image.data, version, &sign::load_key(key)?);
+            std::fs::write(&out, &signed)?;
+            println!(
+                "signed image of {} bytes (version {}.{}.{}) written to {}",
+                signed.len(),
+                version.major,
+                version.minor,
+                version.patch,
+                out.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn parse_version(s: &str) -> Result<Version, String> {
+    let parts: Vec<_> = s.split('.').map(str::parse).collect();
+    match parts[..] {
+        [Ok(major), Ok(minor), Ok(patch)] => Ok(Version {
+            major,
+            minor,
+            patch,
+        }),
+        _ => Err(format!("expected major.minor.patch, got {}", s)),
+    }
+}
+
+fn update<P: Port>(master: &mut Master<P>, dev: DevId, image: &Image) -> Result<(), Error> {
+    let start = Instant::now();
+    master.update(dev, &image.data, |done, size| {
+        print!("\r{:>8} / {} bytes", done, size);
+        let _ = std::io::stdout().flush();
+    })?;
+    println!("\ncommitted in {:.1} s", start.elapsed().as_secs_f32());
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! Messages larger than a frame
+//!
+//! `send_message` splits a message into `Command::Fragment`s of the MTU negotiated with
+//! the servant (`negotiate_mtu`, done on first use), then reads the reply message of the
+//! servant fragment by fragment (`Command::FragmentRead`) into the caller's buffer.
+
+use crate::{Error, Master, Port};
+use master_and_servant::{Capabilities, Command, DevId, Mtu, Response, FRAGMENT};
+
+impl<P: Port> Master<P> {
+    /// Agree on the fragment size with `dev`
+    pub fn negotiate_mtu(&mut self, dev: DevId) -> Result<Mtu, Error> {
+        self.require(dev, Capabilities::BULK)?;
+        let cmd = Command::Mtu {
+            mtu: FRAGMENT as u16,
+            dev,
+        };
+        match self.send_to(dev, cmd)? {
+            Response::Mtu(mtu) if (1..=FRAGMENT).contains(&(mtu.fragment as usize)) => {
+                self.mtus.insert(dev, mtu);
+                Ok(mtu)
+            }
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// The sizes negotiated with `dev`, if any
+    pub fn mtu(&self, dev: DevId) -> Option<Mtu> {
+        self.mtus.get(&dev).copied()
+    }
+
+    /// Send `message` to `dev`, returns the reply message, read into `reply`
+    pub fn send_message<'a>(
+        &mut self,
+        dev: DevId,
+        message: &[u8],
+        reply: &'a mut [u8],
+    ) -> Result<&'a [u8], Error> {
+        self.require(dev, Capabilities::BULK)?;
+        let mtu = match self.mtu(dev) {
+            Some(mtu) => mtu,
+            None => self.negotiate_mtu(dev)?,
+        };
+        // an empty message is sent as a single empty fragment
+        let mut fragments: Vec<_> = message.chunks(mtu.fragment as usize).collect();
+        if fragments.is_empty() {
+            fragments.push(&[]);
+        }
+        let count = fragments.len();
+        if message.len() > mtu.message as usize || count > u16::MAX as usize {
+            return Err(Error::TooLarge {
+                size: message.len(),
+                max: mtu.message as usize,
+            });
+        }
+        let msg = self.msg;
+        self.msg = self.msg.wrapping_add(1);
+
+        let mut size = None;
+        for (index, data) in fragments.into_iter().enumerate() {
+            let cmd = Command::Fragment {
+                msg,
+                index: index as u16,
+                count: count as u16,
+                data: data.iter().copied().collect(),
+                dev,
+            };
+            match self.send_to(dev, cmd)? {
+                Response::SetOk if index + 1 < count => {}
+                Response::Message { msg: m, size: n } if m == msg && index + 1 == count => {
+                    size = Some(n as usize)
+                }
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        let size = size.unwrap_or(0);
+        if size > reply.len() {
+            return Err(Error::TooLarge {
+                size,
+                max: reply.len(),
+            });
+        }
+
+        let mut len = 0;
+        let mut index = 0;
+        while len < size {
+            match self.send_to(dev, Command::FragmentRead { msg, index, dev })? {
+                Response::Fragment {
+                    msg: m,
+                    index: i,
+                    data,
+                    ..
+                } if m == msg && i == index && !data.is_empty() && len + data.len() <= size => {
+                    reply[len..len + data.len()].copy_from_slice(&data);
+                    len += data.len();
+                    index += 1;
+                }
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        Ok(&reply[..size])
+    }
+}
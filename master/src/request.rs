@@ -0,0 +1,689 @@
+//! Stop-and-wait request/response
+//!
+//! Each `Command` is wrapped in a `Request` carrying a fresh sequence number. If no
+//! matching `Reply` arrives in time, the request is retransmitted (with the same sequence
+//! number, so the servant can suppress duplicates) and the timeout is increased. Timeouts
+//! run from when the port is expected to have sent the request (see `Master::set_baud`),
+//! so long frames at low baud rates do not eat into them.
+
+use crate::link::Links;
+use crate::{byte_time, Error, Port, BAUD};
+use master_and_servant::{
+    serialize_crc_cobs, Capabilities, Command, DevId, ErrorCode, Frame, FrameAccumulator,
+    FrameError, Hello, Id, Micros, MsgId, Mtu, Op, Outcome, Parameter, Reply, Request, Response,
+    Seq, Value, WireSize, BATCH, BROADCAST, SEQ_UNKNOWN, SLOT_BYTES,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Retransmission policy of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retry {
+    /// Time to wait for the reply to the first transmission, once sent
+    pub timeout: Duration,
+    /// Number of retransmissions
+    pub retries: u32,
+    /// The timeout is multiplied by `backoff` for each retransmission
+    pub backoff: u32,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(200),
+            retries: 3,
+            backoff: 2,
+        }
+    }
+}
+
+/// Outcome of a multicast
+#[derive(Debug, Default)]
+pub struct Multicast {
+    /// Responses by servant, in slot order
+    pub responses: Vec<(DevId, Response)>,
+    /// Servants addressed by the mask that did not reply
+    pub silent: Vec<DevId>,
+}
+
+/// Outcome of `Master::probe`
+pub(crate) enum Probe {
+    Silent,
+    /// A single servant answered
+    Single(Response),
+    /// Several servants answered, their replies collided
+    Collision,
+}
+
+/// Outcome of a single transmission
+enum Attempt {
+    Reply(Response),
+    /// The servant could not decode the request
+    Rejected(ErrorCode, u32),
+    Timeout,
+}
+
+/// An unsolicited value pushed by a servant, see `Master::subscribe`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Notification {
+    pub dev: DevId,
+    pub id: Id,
+    pub parameter: Parameter,
+    pub value: Value,
+    /// Servant time of sampling, the master time base once synchronised (`Master::sync_time`)
+    pub timestamp: Micros,
+}
+
+/// Request/response over a port (typically a `SerialPort`)
+///
+/// Replies with another sequence number (late replies to earlier requests) or from
+/// another servant than the addressed one are discarded, as are corrupted frames.
+/// Notifications are passed to the `on_notify` callback whenever frames are received,
+/// and the links of watched servants are updated (see `watch`).
+pub struct Master<P> {
+    port: P,
+    notify: Option<Box<dyn FnMut(Notification) + Send>>,
+    pub(crate) links: Links,
+    /// Start of the master clock, see `now`
+    pub(crate) epoch: Instant,
+    seq: Seq,
+    /// Sizes negotiated with the servants, see `negotiate_mtu`
+    pub(crate) mtus: HashMap<DevId, Mtu>,
+    /// Id of the next message
+    pub(crate) msg: MsgId,
+    /// Parameters of the servants greeted, see `hello`
+    pub(crate) peers: HashMap<DevId, Hello>,
+    retry: Retry,
+    slot_time: Duration,
+    byte_time: Duration,
+    /// When the frames written so far are expected to be sent
+    tx_idle: Instant,
+    out_buf: <Request as Frame>::FrameBuf,
+    acc: FrameAccumulator<{ Reply::MAX_FRAME_SIZE }>,
+}
+
+impl<P: Port> Master<P> {
+    pub fn new(port: P) -> Self {
+        // start at an arbitrary sequence number, so requests of a restarted master
+        // are not taken for retransmissions of the previous session
+        let seq = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(SEQ_UNKNOWN, |t| t.subsec_nanos() as Seq);
+        Self {
+            port,
+            notify: None,
+            links: Links::new(),
+            epoch: Instant::now(),
+            seq,
+            mtus: HashMap::new(),
+            msg: seq,
+            peers: HashMap::new(),
+            retry: Retry::default(),
+            slot_time: byte_time(BAUD) * SLOT_BYTES as u32,
+            byte_time: byte_time(BAUD),
+            tx_idle: Instant::now(),
+            out_buf: Request::frame_buf(),
+            acc: FrameAccumulator::new(),
+        }
+    }
+
+    /// Set the default retransmission policy
+    pub fn set_retry(&mut self, retry: Retry) {
+        self.retry = retry;
+    }
+
+    /// The default retransmission policy
+    pub fn retry(&self) -> Retry {
+        self.retry
+    }
+
+    /// Set the multicast reply slot length, `SLOT_BYTES` byte times at the link baud rate
+    pub fn set_slot_time(&mut self, slot_time: Duration) {
+        self.slot_time = slot_time;
+    }
+
+    /// Set the link baud rate (`BAUD` by default), sets the slot length accordingly
+    ///
+    /// The baud rate tells when pipelined requests queued by the port are sent.
+    pub fn set_baud(&mut self, baud: u32) {
+        self.byte_time = byte_time(baud);
+        self.slot_time = self.byte_time * SLOT_BYTES as u32;
+    }
+
+    /// The underlying port
+    pub fn port(&mut self) -> &mut P {
+        &mut self.port
+    }
+
+    fn next_seq(&mut self) -> Seq {
+        self.seq = self.seq.wrapping_add(1);
+        if self.seq == SEQ_UNKNOWN {
+            self.seq = self.seq.wrapping_add(1);
+        }
+        self.seq
+    }
+
+    /// Send `cmd` and wait for the matching response, using the default retransmission policy
+    pub fn request(&mut self, cmd: Command) -> Result<Response, Error> {
+        self.request_with(cmd, self.retry)
+    }
+
+    /// Send `cmd` to `dev` and wait for the response
+    pub fn send_to(&mut self, dev: DevId, cmd: Command) -> Result<Response, Error> {
+        self.request(cmd.with_dev(dev))
+    }
+
+    /// Read parameter `id`/`parameter` of `dev`, the value must be of type `T`
+    pub fn get<T: TryFrom<Value, Error = Value>>(
+        &mut self,
+        dev: DevId,
+        id: Id,
+        parameter: Parameter,
+    ) -> Result<T, Error> {
+        match self.send_to(dev, Command::Get(id, parameter, dev))? {
+            Response::Data(i, p, value, _) if i == id && p == parameter => {
+                T::try_from(value).map_err(Error::TypeMismatch)
+            }
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Write `value` to parameter `id` of `dev` (its parameter 0, see `Command::Set`)
+    pub fn set(&mut self, dev: DevId, id: Id, value: impl Into<Value>) -> Result<(), Error> {
+        match self.send_to(dev, Command::Set(id, value.into(), dev))? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Write `value` to parameter `id` of `dev` at the master time `at` (see `sync_time`)
+    ///
+    /// Fails with `ErrorCode::TooLate` if `at` has passed when the servant receives the
+    /// request, `ErrorCode::QueueFull` if the servant cannot queue another value.
+    pub fn set_at(
+        &mut self,
+        dev: DevId,
+        id: Id,
+        value: impl Into<Value>,
+        at: Micros,
+    ) -> Result<(), Error> {
+        let cmd = Command::SetAt {
+            id,
+            value: value.into(),
+            at,
+            dev,
+        };
+        match self.send_to(dev, cmd)? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Stage `value` for parameter `id` of `dev`, written on the next `trigger`
+    pub fn stage(&mut self, dev: DevId, id: Id, value: impl Into<Value>) -> Result<(), Error> {
+        match self.send_to(dev, Command::Stage(id, value.into(), dev))? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Have all servants write their staged values at once, by broadcast
+    pub fn trigger(&mut self) -> Result<(), Error> {
+        self.broadcast(Command::Trigger(BROADCAST))
+    }
+
+    /// Execute `ops` on `dev`, sending up to `BATCH` operations per request
+    ///
+    /// Returns the outcome of each operation, in the order of `ops`.
+    /// Servants without `Capabilities::BATCH` are sent one request per operation.
+    pub fn batch(&mut self, dev: DevId, ops: &[Op]) -> Result<Vec<Outcome>, Error> {
+        if !self.supports(dev, Capabilities::BATCH) {
+            return ops.iter().map(|op| self.single(dev, *op)).collect();
+        }
+        let mut outcomes = Vec::with_capacity(ops.len());
+        for chunk in ops.chunks(BATCH) {
+            let batch = Command::Batch(chunk.iter().copied().collect(), dev);
+            match self.send_to(dev, batch)? {
+                Response::Batch(items) if items.len() == chunk.len() => outcomes.extend(items),
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Execute `op` by a request of its own
+    fn single(&mut self, dev: DevId, op: Op) -> Result<Outcome, Error> {
+        let cmd = match op {
+            Op::Get(id, parameter) => Command::Get(id, parameter, dev),
+            Op::Set(id, value) => Command::Set(id, value, dev),
+        };
+        match self.send_to(dev, cmd) {
+            Ok(Response::Data(id, parameter, value, _)) if matches!(op, Op::Get(..)) => {
+                Ok(Outcome::Data(id, parameter, value))
+            }
+            Ok(Response::SetOk) if matches!(op, Op::Set(..)) => Ok(Outcome::SetOk),
+            Ok(resp) => Err(Error::Unexpected(resp)),
+            Err(Error::Servant { code, detail }) => Ok(Outcome::Error(code, detail)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Ask `dev` to push parameter `id`/`parameter` every `period` (zero for never) and
+    /// whenever it changes by more than `deadband` (`f32::INFINITY` for never)
+    ///
+    /// Notifications are passed to the `on_notify` callback (or `notifications` channel).
+    pub fn subscribe(
+        &mut self,
+        dev: DevId,
+        id: Id,
+        parameter: Parameter,
+        period: Duration,
+        deadband: f32,
+    ) -> Result<(), Error> {
+        self.require(dev, Capabilities::TELEMETRY)?;
+        let period_ms = period.as_millis().try_into().unwrap_or(u32::MAX);
+        let cmd = Command::Subscribe {
+            id,
+            parameter,
+            period_ms,
+            deadband,
+            dev,
+        };
+        match self.send_to(dev, cmd)? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Cancel the subscription of `dev` to `id`/`parameter`
+    pub fn unsubscribe(&mut self, dev: DevId, id: Id, parameter: Parameter) -> Result<(), Error> {
+        match self.send_to(dev, Command::Unsubscribe { id, parameter, dev })? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Call `f` for each notification received, replacing the previous callback
+    pub fn on_notify(&mut self, f: impl FnMut(Notification) + Send + 'static) {
+        self.notify = Some(Box::new(f));
+    }
+
+    /// Receive the notifications through a channel, replacing the `on_notify` callback
+    pub fn notifications(&mut self) -> mpsc::Receiver<Notification> {
+        let (tx, rx) = mpsc::channel();
+        self.on_notify(move |notification| {
+            // the receiver may be gone, drop the notification
+            let _ = tx.send(notification);
+        });
+        rx
+    }
+
+    /// Receive notifications for `duration`, other frames are discarded
+    pub fn listen(&mut self, duration: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + duration;
+        while self.receive_frame_until(deadline)?.is_some() {}
+        Ok(())
+    }
+
+    /// Send `cmd` to all servants, without reply
+    ///
+    /// As broadcasts are not acknowledged, the request is sent `1 + retries` times
+    /// (default retransmission policy) with the same sequence number, servants execute it once.
+    pub fn broadcast(&mut self, cmd: Command) -> Result<(), Error> {
+        let request = Request {
+            seq: self.next_seq(),
+            multicast: false,
+            cmd: cmd.with_dev(BROADCAST),
+        };
+        for _ in 0..=self.retry.retries {
+            self.send(&request)?;
+        }
+        Ok(())
+    }
+
+    /// Send `cmd` to all servants in `mask` and collect their responses
+    ///
+    /// Each servant replies in its own time slot. While servants are silent, the request
+    /// is retransmitted (default retransmission policy), servants that already executed
+    /// the command reply again without executing it twice. Servants silent to the end
+    /// count as a miss of their link (see `watch`).
+    pub fn multicast(&mut self, mask: DevId, cmd: Command) -> Result<Multicast, Error> {
+        if mask == BROADCAST {
+            return Err(Error::Broadcast);
+        }
+        let request = Request {
+            seq: self.next_seq(),
+            multicast: true,
+            cmd: cmd.with_dev(mask),
+        };
+        let devs: Vec<DevId> = (0..DevId::BITS)
+            .map(|bit| 1 << bit)
+            .filter(|dev| mask & dev != 0)
+            .collect();
+        let slots = self.slot_time * devs.len() as u32;
+        self.acc.reset();
+
+        let mut responses = BTreeMap::new();
+        let mut timeout = self.retry.timeout;
+        for _ in 0..=self.retry.retries {
+            let sent = self.send(&request)?;
+            let deadline = sent + slots + timeout;
+            while responses.len() < devs.len() {
+                let Some(reply) = self.receive_until(deadline)? else {
+                    break;
+                };
+                if reply.seq == request.seq && devs.contains(&reply.dev) {
+                    responses.insert(reply.dev, reply.resp);
+                }
+            }
+            if responses.len() == devs.len() {
+                break;
+            }
+            timeout = timeout.saturating_mul(self.retry.backoff);
+        }
+
+        let silent: Vec<DevId> = devs
+            .into_iter()
+            .filter(|dev| !responses.contains_key(dev))
+            .collect();
+        for dev in &silent {
+            self.links.missed(*dev);
+        }
+        Ok(Multicast {
+            silent,
+            responses: responses.into_iter().collect(),
+        })
+    }
+
+    /// Send `cmd`, expecting any number of servants to answer
+    ///
+    /// After the first reply (or corrupted frame) the master keeps listening for one
+    /// multicast slot, a second reply or a corrupted frame is taken for a collision.
+    /// A request yielding only corrupted frames is retransmitted (default retransmission
+    /// policy), and reported as a collision if the last attempt is corrupted too. Silence
+    /// counts as a miss of the link to the addressed servant (see `watch`).
+    pub(crate) fn probe(&mut self, cmd: Command) -> Result<Probe, Error> {
+        let dev = cmd.dev();
+        let request = Request {
+            seq: self.next_seq(),
+            multicast: false,
+            cmd,
+        };
+        self.acc.reset();
+
+        let mut timeout = self.retry.timeout;
+        for attempt in 0..=self.retry.retries {
+            let sent = self.send(&request)?;
+            let mut deadline = sent + timeout;
+            let mut responses = Vec::new();
+            let mut corrupted = false;
+            while let Some(frame) = self.receive_frame_until(deadline)? {
+                match frame {
+                    Ok(reply) if reply.seq == request.seq && reply.dev == dev => {
+                        responses.push(reply.resp)
+                    }
+                    // stale reply
+                    Ok(_) => continue,
+                    Err(_) => corrupted = true,
+                }
+                deadline = deadline.min(Instant::now() + self.slot_time);
+            }
+            let last = attempt == self.retry.retries;
+            match (responses.as_slice(), corrupted) {
+                ([], false) => {}
+                ([_], false) => return Ok(Probe::Single(responses.remove(0))),
+                ([], true) if !last => {}
+                _ => return Ok(Probe::Collision),
+            }
+            timeout = timeout.saturating_mul(self.retry.backoff);
+        }
+        self.links.missed(dev);
+        Ok(Probe::Silent)
+    }
+
+    /// Send `cmd` and wait for the matching response, retransmitting according to `retry`
+    pub fn request_with(&mut self, cmd: Command, retry: Retry) -> Result<Response, Error> {
+        if cmd.dev() == BROADCAST {
+            return Err(Error::Broadcast);
+        }
+        let request = Request {
+            seq: self.next_seq(),
+            multicast: false,
+            cmd,
+        };
+        self.acc.reset();
+
+        let mut timeout = retry.timeout;
+        let mut last = Attempt::Timeout;
+        for _ in 0..=retry.retries {
+            let sent = self.send(&request)?;
+            last = self.wait_reply(&request, sent + timeout)?;
+            if let Attempt::Reply(resp) = last {
+                return servant_error(resp);
+            }
+            timeout = timeout.saturating_mul(retry.backoff);
+        }
+        match last {
+            Attempt::Rejected(code, detail) => Err(Error::Servant { code, detail }),
+            _ => {
+                self.links.missed(request.cmd.dev());
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    /// Wait for the reply to `request` until `deadline`
+    ///
+    /// A `Response::Error` with `SEQ_UNKNOWN` from the addressed servant rejects the
+    /// request (see `Servant::with_error_replies`).
+    fn wait_reply(&mut self, request: &Request, deadline: Instant) -> Result<Attempt, Error> {
+        let dev = request.cmd.dev();
+        while let Some(reply) = self.receive_until(deadline)? {
+            if reply.dev != dev {
+                continue;
+            }
+            if let (SEQ_UNKNOWN, &Response::Error(code, detail)) = (reply.seq, &reply.resp) {
+                return Ok(Attempt::Rejected(code, detail));
+            }
+            if reply.seq == request.seq {
+                return Ok(Attempt::Reply(reply.resp));
+            }
+            // stale or mismatched reply, keep waiting
+        }
+        Ok(Attempt::Timeout)
+    }
+
+    /// Send all `cmds`, keeping up to `window` requests outstanding
+    ///
+    /// Each request is retransmitted on its own (selective retransmit) according to the
+    /// default retransmission policy, its timeout running from when the port is expected
+    /// to have sent it (after the requests queued ahead, see `set_baud`). The window does
+    /// not advance past the oldest outstanding request, so a servant receive window
+    /// (`Servant<W>`) at least as large as `window` suppresses all duplicates. Requests the
+    /// servant could not decode cannot be attributed, the affected request is retransmitted
+    /// on timeout. Responses are returned in the order of `cmds`, `Response::Error` as
+    /// `Error::Servant`.
+    pub fn pipeline(
+        &mut self,
+        cmds: &[Command],
+        window: usize,
+    ) -> Result<Vec<Result<Response, Error>>, Error> {
+        struct Outstanding {
+            index: usize,
+            request: Request,
+            timeout: Duration,
+            deadline: Instant,
+            retries: u32,
+        }
+
+        let retry = self.retry;
+        let window = window.max(1);
+        let mut results: Vec<Option<Result<Response, Error>>> = cmds.iter().map(|_| None).collect();
+        let mut outstanding: Vec<Outstanding> = Vec::with_capacity(window);
+        let mut cmds = cmds.iter().cloned().enumerate().peekable();
+        self.acc.reset();
+
+        loop {
+            // fill the window, which starts at the oldest outstanding request
+            loop {
+                let base = outstanding.iter().map(|o| o.index).min();
+                let Some((index, cmd)) =
+                    cmds.next_if(|(i, _)| base.is_none_or(|b| *i < b + window))
+                else {
+                    break;
+                };
+                if cmd.dev() == BROADCAST {
+                    results[index] = Some(Err(Error::Broadcast));
+                    continue;
+                }
+                let request = Request {
+                    seq: self.next_seq(),
+                    multicast: false,
+                    cmd,
+                };
+                let sent = self.send(&request)?;
+                outstanding.push(Outstanding {
+                    index,
+                    request,
+                    timeout: retry.timeout,
+                    deadline: sent + retry.timeout,
+                    retries: 0,
+                });
+            }
+
+            let Some(deadline) = outstanding.iter().map(|o| o.deadline).min() else {
+                break;
+            };
+
+            match self.receive_until(deadline)? {
+                Some(reply) => {
+                    if let Some(i) = outstanding.iter().position(|o| {
+                        o.request.seq == reply.seq && o.request.cmd.dev() == reply.dev
+                    }) {
+                        let done = outstanding.swap_remove(i);
+                        results[done.index] = Some(servant_error(reply.resp));
+                    }
+                }
+                None => {
+                    // selective retransmit of the expired requests
+                    let now = Instant::now();
+                    let mut i = 0;
+                    while i < outstanding.len() {
+                        if outstanding[i].deadline > now {
+                            i += 1;
+                        } else if outstanding[i].retries >= retry.retries {
+                            let failed = outstanding.swap_remove(i);
+                            self.links.missed(failed.request.cmd.dev());
+                            results[failed.index] = Some(Err(Error::Timeout));
+                        } else {
+                            let o = &mut outstanding[i];
+                            o.retries += 1;
+                            o.timeout = o.timeout.saturating_mul(retry.backoff);
+                            let request = o.request.clone();
+                            let sent = self.send(&request)?;
+                            outstanding[i].deadline = sent + outstanding[i].timeout;
+                            i += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|r| r.unwrap_or(Err(Error::Timeout)))
+            .collect())
+    }
+
+    /// Write `request` to the port, returns when it is expected to be sent
+    fn send(&mut self, request: &Request) -> Result<Instant, Error> {
+        let to_write = serialize_crc_cobs(request, &mut self.out_buf)?;
+        self.port.write_all(to_write)?;
+        // the port queues the frame behind those not yet sent
+        let start = self.tx_idle.max(Instant::now());
+        self.tx_idle = start + self.byte_time * to_write.len() as u32;
+        Ok(self.tx_idle)
+    }
+
+    /// Receive the next reply before `deadline`, `None` on timeout
+    ///
+    /// Corrupted frames are dropped, we rely on retransmission.
+    fn receive_until(&mut self, deadline: Instant) -> Result<Option<Reply>, Error> {
+        while let Some(frame) = self.receive_frame_until(deadline)? {
+            if let Ok(reply) = frame {
+                return Ok(Some(reply));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Receive the next frame before `deadline`, `None` on timeout
+    ///
+    /// Notifications are passed on to the callback, as are unsolicited heartbeats to the
+    /// link monitor.
+    fn receive_frame_until(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<Option<Result<Reply, FrameError>>, Error> {
+        let mut byte = [0u8; 1];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            self.port.set_read_timeout(remaining)?;
+            match self.port.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    return Ok(None)
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+            let reply = match self.acc.push::<Reply>(byte[0]) {
+                Some(Ok(reply)) => reply,
+                Some(Err(err)) => return Ok(Some(Err(err))),
+                None => continue,
+            };
+            let session = match reply.resp {
+                Response::Heartbeat(session) => Some(session),
+                _ => None,
+            };
+            self.links.seen(reply.dev, session);
+            match reply {
+                Reply {
+                    dev,
+                    resp: Response::Notify(id, parameter, value, timestamp),
+                    ..
+                } => {
+                    if let Some(notify) = &mut self.notify {
+                        notify(Notification {
+                            dev,
+                            id,
+                            parameter,
+                            value,
+                            timestamp,
+                        });
+                    }
+                }
+                Reply {
+                    seq: SEQ_UNKNOWN,
+                    resp: Response::Heartbeat(_),
+                    ..
+                } => {}
+                reply => return Ok(Some(Ok(reply))),
+            }
+        }
+    }
+}
+
+/// `Response::Error` as `Error::Servant`
+fn servant_error(resp: Response) -> Result<Response, Error> {
+    match resp {
+        Response::Error(code, detail) => Err(Error::Servant { code, detail }),
+        resp => Ok(resp),
+    }
+}
@@ -0,0 +1,101 @@
+//! Image signing
+//!
+//! `sign` appends the trailer checked by the servant's `Verifier`. Keys are stored as
+//! the hex encoded 32 byte ed25519 seed, the servant is built with the matching
+//! `PublicKey` (stored as its 32 raw bytes by `save_public_key`).
+
+pub use ed25519_compact::KeyPair;
+use ed25519_compact::Seed;
+use master_and_servant::{digest, signed_message, trailer, PublicKey, Version};
+use std::fs::OpenOptions;
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+/// A new random key pair
+pub fn generate() -> KeyPair {
+    KeyPair::from_seed(Seed::generate())
+}
+
+/// The public key of `key`, for the servant's `Verifier`
+pub fn public_key(key: &KeyPair) -> PublicKey {
+    *key.pk
+}
+
+/// `image` of `version` followed by its signature trailer
+pub fn sign(image: &[u8], version: Version, key: &KeyPair) -> Vec<u8> {
+    let signature = key.sk.sign(signed_message(&digest(image), version), None);
+    let mut signed = image.to_vec();
+    signed.extend_from_slice(&trailer(version, &signature));
+    signed
+}
+
+/// Read a key written by `save_key`
+pub fn load_key(path: impl AsRef<Path>) -> io::Result<KeyPair> {
+    let hex = std::fs::read_to_string(path)?;
+    let hex = hex.trim();
+    let mut seed = [0; Seed::BYTES];
+    if hex.len() != 2 * seed.len() {
+        return Err(invalid());
+    }
+    for (i, byte) in seed.iter_mut().enumerate() {
+        *byte = hex
+            .get(2 * i..2 * i + 2)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+    Ok(KeyPair::from_seed(Seed::new(seed)))
+}
+
+/// Write the seed of `key` to `path`, creating its directory
+///
+/// On Unix the file is only readable by its owner (mode 0600), also when it existed.
+pub fn save_key(path: impl AsRef<Path>, key: &KeyPair) -> io::Result<()> {
+    let hex: String = key.sk.seed().iter().map(|b| format!("{:02x}", b)).collect();
+    create_parent(path.as_ref())?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // the mode only applies to a new file
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all((hex + "\n").as_bytes())
+}
+
+/// Write the public key of `key` to `path`, e.g., included by the servant at build time
+pub fn save_public_key(path: impl AsRef<Path>, key: &KeyPair) -> io::Result<()> {
+    create_parent(path.as_ref())?;
+    std::fs::write(path, public_key(key))
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "not a hex encoded ed25519 seed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_round_trip() {
+        let path = std::env::temp_dir().join(format!("master-sign-{}.key", std::process::id()));
+        let key = generate();
+        save_key(&path, &key).unwrap();
+        assert_eq!(load_key(&path).unwrap().sk, key.sk);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,653 @@
+//! Simulated serial link with servants, for host side testing and measurements
+//!
+//! Bytes are delivered in real time according to the simulated baud rate. Each servant
+//! decodes the frames sent by the master and replies after a turnaround delay, frames
+//! (in both directions) can be dropped at random to exercise retransmission.
+//!
+//! Servants transmit independently, replies overlapping in time (e.g., two servants
+//! sharing a `DevId`) collide and garble each other.
+//!
+//! Subscribed parameters are polled (and scheduled values applied) every `TICK` while
+//! the master reads, notifications (and heartbeats of servants sending them on their
+//! own) are sent as soon as the servant's transmitter is idle. Servants can be powered
+//! off, and rebooted with a new `Session` when powered on again.
+//!
+//! Messages sent to a servant (`Master::send_message`) are echoed as its reply.
+//!
+//! Servant clocks count from the servant creation (or reboot, see `with_uptime`), the
+//! `Command::TimeSync` timestamps are the exact frame arrival and reply times.
+
+use crate::{byte_time, Port};
+use master_and_servant::{
+    serialize_crc_cobs, Access, BlobId, BlobProvider, Blobs, Capabilities, Clock, DevId, ErrorCode,
+    Frame, FrameAccumulator, FrameError, Id, Messages, Micros, Outgoing, Parameter,
+    ParameterRegistry, PublicKey, Reply, Request, Response, Schedule, Servant, Session, Slot,
+    Staging, Subscriptions, Uid, Updater, Value, Verifier, Version, WireSize, SLOT_BYTES,
+};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Read, Write};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Parameters of simulated servants, one for each `Id` below
+pub const SIM_PARAMETERS: usize = 64;
+
+/// Receive window of simulated servants
+pub const SIM_WINDOW: usize = 16;
+
+/// Telemetry subscriptions of simulated servants
+pub const SIM_SUBSCRIPTIONS: usize = 8;
+
+/// Queued values (`Command::SetAt`/`Command::Stage`) of simulated servants
+pub const SIM_SCHEDULE: usize = 8;
+
+/// Size of the firmware staging area of simulated servants
+pub const SIM_STAGING: u32 = 1 << 20;
+
+/// Capabilities of simulated servants, `Capabilities::AUTH` is added by an update key
+pub const SIM_CAPABILITIES: Capabilities = Capabilities::BATCH
+    .union(Capabilities::TELEMETRY)
+    .union(Capabilities::BULK);
+
+/// Largest blob simulated servants accept
+pub const SIM_BLOB: u32 = 1 << 20;
+
+/// Largest message (and reply) of simulated servants
+pub const SIM_MESSAGE: usize = 4096;
+
+/// Time after which simulated servants discard an incomplete message
+pub const SIM_MESSAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Interval at which simulated servants poll their subscriptions
+const TICK: Duration = Duration::from_millis(1);
+
+/// A simulated servant with a `ParameterRegistry` of `SIM_PARAMETERS` parameters, each `Id`
+/// has parameter 0 holding `Value::U32(0)` and read-write unless declared otherwise (see
+/// `with_parameter`)
+pub struct SimServant {
+    servant: Servant<SIM_WINDOW>,
+    parameters: ParameterRegistry<SIM_PARAMETERS>,
+    subscriptions: Subscriptions<SIM_SUBSCRIPTIONS>,
+    schedule: Schedule<SIM_SCHEDULE>,
+    clock: Clock,
+    updater: Updater<SimStaging>,
+    /// Key update images must be signed with
+    update_key: Option<PublicKey>,
+    /// Reported by `Response::Hello`
+    capabilities: Capabilities,
+    /// Protocol version reported instead of `PROTOCOL`
+    protocol: Option<u16>,
+    blobs: Blobs<SimBlobs>,
+    /// Messages are echoed
+    messages: Messages<Vec<u8>>,
+    /// Start of the servant clock
+    epoch: Instant,
+    powered: bool,
+    /// Interval of servant initiated heartbeats, and when the next one is due
+    heartbeat: Option<(Duration, Instant)>,
+    /// When the servant transmitter becomes idle
+    tx_idle: Instant,
+    /// Number of commands executed (retransmissions excluded)
+    pub executed: usize,
+    /// Scheduled and staged values applied, with the servant time
+    pub applied: Vec<(Micros, Id, Value)>,
+}
+
+impl SimServant {
+    /// Schedule the transmission of `bytes`, starting not before `earliest`
+    fn transmit(
+        &mut self,
+        bytes: &[u8],
+        earliest: Instant,
+        byte_time: Duration,
+    ) -> Vec<(Instant, u8)> {
+        let mut at = self.tx_idle.max(earliest);
+        let timed = bytes
+            .iter()
+            .map(|byte| {
+                at += byte_time;
+                (at, *byte)
+            })
+            .collect();
+        self.tx_idle = at;
+        timed
+    }
+
+    pub fn new(dev: DevId) -> Self {
+        Self {
+            servant: Servant::new(dev)
+                .with_session(session(0))
+                .with_capabilities(SIM_CAPABILITIES),
+            parameters: ParameterRegistry::new(core::array::from_fn(|id| {
+                Slot::new(id as Id, 0, Access::ReadWrite, Value::U32(0))
+            })),
+            subscriptions: Subscriptions::new(),
+            schedule: Schedule::new(),
+            clock: Clock::new(),
+            updater: Updater::new(SimStaging::default()),
+            update_key: None,
+            capabilities: SIM_CAPABILITIES,
+            protocol: None,
+            blobs: Blobs::new(SimBlobs::default()),
+            messages: messages(),
+            epoch: Instant::now(),
+            powered: true,
+            heartbeat: None,
+            tx_idle: Instant::now(),
+            executed: 0,
+            applied: Vec::new(),
+        }
+    }
+
+    /// Set the unique hardware id, create with `UNASSIGNED` to take part in address assignment
+    pub fn with_uid(mut self, uid: Uid) -> Self {
+        self.servant = self.servant.with_uid(uid);
+        self
+    }
+
+    /// Current device id
+    pub fn dev(&self) -> DevId {
+        self.servant.dev()
+    }
+
+    /// Set the reported firmware version
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.servant = self.servant.with_version(version);
+        self.updater = self.updater();
+        self
+    }
+
+    /// Only accept update images signed with `key`, and not older than the firmware version
+    pub fn with_update_key(mut self, key: PublicKey) -> Self {
+        self.update_key = Some(key);
+        self.updater = self.updater();
+        let capabilities = self.capabilities.union(Capabilities::AUTH);
+        self.with_capabilities(capabilities)
+    }
+
+    /// Report `capabilities` (`SIM_CAPABILITIES` by default), as if built without the
+    /// others, the commands are served anyway
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self.servant = self.servant.with_capabilities(capabilities);
+        self
+    }
+
+    /// A new updater, checking signatures if there is an update key
+    fn updater(&self) -> Updater<SimStaging> {
+        let updater = Updater::new(SimStaging::default());
+        match self.update_key {
+            Some(key) => updater.with_verifier(Verifier::new(key, self.servant.info().version)),
+            None => updater,
+        }
+    }
+
+    /// Send a heartbeat every `interval` on its own, besides answering `Command::Heartbeat`
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some((interval, Instant::now()));
+        self
+    }
+
+    /// Start the servant clock at `uptime`, as if booted that long ago
+    pub fn with_uptime(mut self, uptime: Duration) -> Self {
+        self.epoch -= uptime;
+        self
+    }
+
+    /// Servant time at `at`, the local time until synchronised
+    pub fn time(&self, at: Instant) -> Micros {
+        self.clock.now(self.local(at))
+    }
+
+    /// Time of the servant's own clock
+    fn local(&self, at: Instant) -> Micros {
+        (at - self.epoch).as_micros() as Micros
+    }
+
+    /// Current session, the boot counter starts at 0
+    pub fn session(&self) -> Session {
+        self.servant.session()
+    }
+
+    /// Power the servant off, it stays silent until powered on
+    pub fn power_off(&mut self) {
+        self.powered = false;
+    }
+
+    /// Power the servant on, rebooting it if it was off
+    ///
+    /// A reboot starts a new session, restarts the clock and loses the receive window,
+    /// subscriptions, clock offset, update and message in progress, the stored values,
+    /// blobs and `DevId` are kept (as if non-volatile).
+    pub fn power_on(&mut self) {
+        if self.powered {
+            return;
+        }
+        let old = &self.servant;
+        let mut servant = Servant::new(old.dev())
+            .with_version(old.info().version)
+            .with_capabilities(self.capabilities)
+            .with_session(session(old.session().boot.wrapping_add(1)));
+        if let Some(uid) = old.uid() {
+            servant = servant.with_uid(uid);
+        }
+        self.servant = servant;
+        self.subscriptions.clear();
+        self.schedule.clear();
+        self.clock = Clock::new();
+        self.updater = self.updater();
+        self.blobs = Blobs::new(std::mem::take(self.blobs.provider_mut()));
+        self.messages = messages();
+        self.epoch = Instant::now();
+        self.powered = true;
+        if let Some((interval, _)) = self.heartbeat {
+            self.heartbeat = Some((interval, self.epoch));
+        }
+    }
+
+    /// The firmware image committed by an update, if any
+    pub fn firmware(&self) -> Option<&[u8]> {
+        let staging = self.updater.staging();
+        staging.committed.map(|size| &staging.area[..size as usize])
+    }
+
+    /// Accept message fragments of at most `mtu` bytes (up to `FRAGMENT`)
+    pub fn with_mtu(mut self, mtu: u16) -> Self {
+        self.messages = messages().with_mtu(mtu);
+        self
+    }
+
+    /// Report another protocol version in `Response::Hello`, as a servant of another
+    /// build would
+    pub fn with_protocol(mut self, protocol: u16) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Provide `blob` with `data`
+    pub fn with_blob(mut self, blob: BlobId, data: Vec<u8>) -> Self {
+        self.blobs.provider_mut().0.insert(blob, data);
+        self
+    }
+
+    /// The contents of `blob`, if any
+    pub fn blob(&self, blob: BlobId) -> Option<&[u8]> {
+        self.blobs.provider().0.get(&blob).map(Vec::as_slice)
+    }
+
+    /// Declare `slot` in place of the parameter of its `Id`, which must be below
+    /// `SIM_PARAMETERS`
+    pub fn with_parameter(mut self, slot: Slot) -> Self {
+        let mut slots = *self.parameters.slots();
+        slots[slot.id() as usize] = slot;
+        self.parameters = ParameterRegistry::new(slots);
+        self
+    }
+
+    /// Store `value` from the application side, as a sensor would, returns `false` if not
+    /// declared or of another type
+    pub fn store(&mut self, id: Id, parameter: Parameter, value: Value) -> bool {
+        self.parameters.store(id, parameter, value)
+    }
+
+    /// Apply the values scheduled until `now`, returns the next notification or
+    /// heartbeat due, if any
+    fn poll(&mut self, now: Instant) -> Option<Reply> {
+        if !self.powered {
+            return None;
+        }
+        let time = self.time(now);
+        let (parameters, applied) = (&mut self.parameters, &mut self.applied);
+        self.schedule.poll(time, |id, value| {
+            parameters.store(id, 0, value);
+            applied.push((time, id, value));
+        });
+        if let Some((interval, due)) = self.heartbeat {
+            if due <= now {
+                self.heartbeat = Some((interval, now + interval));
+                return Some(self.servant.heartbeat());
+            }
+        }
+        let parameters = &self.parameters;
+        let resp = self
+            .subscriptions
+            .poll(time, |id, par| parameters.sample(id, par))?;
+        Some(self.servant.unsolicited(resp))
+    }
+
+    /// Handle a frame received at `received`, to be answered at `reply`
+    fn handle(
+        &mut self,
+        frame: Result<Request, FrameError>,
+        received: Instant,
+        reply: Instant,
+    ) -> Option<Outgoing> {
+        if !self.powered {
+            return None;
+        }
+        let (received, reply) = (self.local(received), self.local(reply));
+        let mut outgoing = self.exec(frame, received, reply);
+        if let Some(out) = &mut outgoing {
+            if let (Response::Hello(hello), Some(protocol)) = (&mut out.reply.resp, self.protocol) {
+                hello.protocol = protocol;
+            }
+        }
+        outgoing
+    }
+
+    /// Execute a frame received at servant time `received`, to be answered at `reply`
+    fn exec(
+        &mut self,
+        frame: Result<Request, FrameError>,
+        received: Micros,
+        reply: Micros,
+    ) -> Option<Outgoing> {
+        let Self {
+            servant,
+            parameters,
+            subscriptions,
+            schedule,
+            clock,
+            updater,
+            blobs,
+            messages,
+            executed,
+            ..
+        } = self;
+        servant.handle(frame, |cmd| {
+            *executed += 1;
+            if let Some(resp) = clock.dispatch(cmd, received, reply) {
+                return resp;
+            }
+            let check = |id, par| parameters.check_read(id, par);
+            if let Some(resp) = subscriptions.dispatch(cmd, check) {
+                return resp;
+            }
+            let check = |id, value: &Value| parameters.check(id, value);
+            if let Some(resp) = schedule.dispatch(cmd, clock.synced(received), check) {
+                return resp;
+            }
+            if let Some(resp) = updater.dispatch(cmd) {
+                return resp;
+            }
+            if let Some(resp) = blobs.dispatch(cmd) {
+                return resp;
+            }
+            let echo = |message: &[u8], reply: &mut [u8]| {
+                reply[..message.len()].copy_from_slice(message);
+                Ok(message.len())
+            };
+            if let Some(resp) = messages.dispatch(cmd, received, echo) {
+                return resp;
+            }
+            // `Get`, `Set` and `Batch`, the others are answered by `Servant`
+            parameters.dispatch(cmd)
+        })
+    }
+}
+
+fn messages() -> Messages<Vec<u8>> {
+    let timeout = SIM_MESSAGE_TIMEOUT.as_micros() as Micros;
+    Messages::new(vec![0; SIM_MESSAGE], vec![0; SIM_MESSAGE], timeout)
+}
+
+/// Session of a boot, the id taken from the clock as a random number generator would do
+fn session(boot: u32) -> Session {
+    let id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |t| t.subsec_nanos());
+    Session { boot, id }
+}
+
+/// Firmware staging area of a simulated servant, in memory
+#[derive(Default)]
+struct SimStaging {
+    area: Vec<u8>,
+    committed: Option<u32>,
+}
+
+impl Staging for SimStaging {
+    fn capacity(&self) -> u32 {
+        SIM_STAGING
+    }
+
+    fn erase(&mut self, size: u32) -> Result<(), ErrorCode> {
+        self.area = vec![0xff; size as usize];
+        self.committed = None;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+        let start = offset as usize;
+        self.area
+            .get_mut(start..start + data.len())
+            .ok_or(ErrorCode::Storage)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let start = offset as usize;
+        let area = self.area.get(start..start + buf.len());
+        buf.copy_from_slice(area.ok_or(ErrorCode::Storage)?);
+        Ok(())
+    }
+
+    fn commit(&mut self, size: u32) -> Result<(), ErrorCode> {
+        self.committed = Some(size);
+        Ok(())
+    }
+}
+
+/// Blobs of any id, written in place
+#[derive(Default)]
+struct SimBlobs(HashMap<BlobId, Vec<u8>>);
+
+impl SimBlobs {
+    fn get(&mut self, blob: BlobId, offset: u32, len: usize) -> Result<&mut [u8], ErrorCode> {
+        let data = self.0.get_mut(&blob).ok_or(ErrorCode::UnknownId)?;
+        let start = offset as usize;
+        data.get_mut(start..start + len)
+            .ok_or(ErrorCode::OutOfRange)
+    }
+}
+
+impl BlobProvider for SimBlobs {
+    fn size(&mut self, blob: BlobId) -> Result<u32, ErrorCode> {
+        let data = self.0.get(&blob).ok_or(ErrorCode::UnknownId)?;
+        Ok(data.len() as u32)
+    }
+
+    fn create(&mut self, blob: BlobId, size: u32) -> Result<(), ErrorCode> {
+        if size > SIM_BLOB {
+            return Err(ErrorCode::OutOfRange);
+        }
+        self.0.insert(blob, vec![0; size as usize]);
+        Ok(())
+    }
+
+    fn read(&mut self, blob: BlobId, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        buf.copy_from_slice(self.get(blob, offset, buf.len())?);
+        Ok(())
+    }
+
+    fn write(&mut self, blob: BlobId, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+        self.get(blob, offset, data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Simulated link, implementing `Port` for the master side
+pub struct SimPort {
+    byte_time: Duration,
+    turnaround: Duration,
+    loss: f64,
+    rng: u64,
+    timeout: Duration,
+    /// When the master to servant direction becomes idle
+    tx_idle: Instant,
+    acc: FrameAccumulator<{ Request::MAX_FRAME_SIZE }>,
+    rx: VecDeque<(Instant, u8)>,
+    /// The servants on the bus
+    pub servants: Vec<SimServant>,
+}
+
+impl SimPort {
+    /// A link at `baud` (8N1, 10 bits per byte), servants replying after `turnaround`
+    pub fn new(baud: u32, turnaround: Duration, servants: Vec<SimServant>) -> Self {
+        Self {
+            byte_time: byte_time(baud),
+            turnaround,
+            loss: 0.0,
+            rng: 0x2545_f491_4f6c_dd1d,
+            timeout: Duration::from_millis(1000),
+            tx_idle: Instant::now(),
+            acc: FrameAccumulator::new(),
+            rx: VecDeque::new(),
+            servants,
+        }
+    }
+
+    /// Drop each frame with probability `loss`
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    fn lost(&mut self) -> bool {
+        // xorshift, deterministic across runs
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng % 1_000_000) as f64 / 1_000_000.0 < self.loss
+    }
+
+    fn transmit_time(&self, len: usize) -> Duration {
+        self.byte_time * len as u32
+    }
+
+    /// A request frame completed at `arrival` on the servant side
+    fn deliver(&mut self, frame: Result<Request, FrameError>, arrival: Instant) {
+        if self.lost() {
+            return;
+        }
+        let mut out_buf = Reply::frame_buf();
+        let mut frames = Vec::new();
+        let ready = arrival + self.turnaround;
+        for servant in &mut self.servants {
+            let Some(Outgoing { reply, slot }) = servant.handle(frame.clone(), arrival, ready)
+            else {
+                continue;
+            };
+            let Ok(bytes) = serialize_crc_cobs(&reply, &mut out_buf) else {
+                continue;
+            };
+            let slot_time = self.byte_time * SLOT_BYTES as u32 * slot;
+            let earliest = ready + slot_time;
+            frames.push(servant.transmit(bytes, earliest, self.byte_time));
+        }
+        self.put(frames);
+    }
+
+    /// Let the servants push the notifications due at `now`
+    fn tick(&mut self, now: Instant) {
+        let mut out_buf = Reply::frame_buf();
+        let mut frames = Vec::new();
+        for servant in &mut self.servants {
+            while let Some(reply) = servant.poll(now) {
+                if let Ok(bytes) = serialize_crc_cobs(&reply, &mut out_buf) {
+                    frames.push(servant.transmit(bytes, now, self.byte_time));
+                }
+            }
+        }
+        self.put(frames);
+    }
+
+    /// Put the frames sent by servants on the line, unless lost
+    fn put(&mut self, frames: Vec<Vec<(Instant, u8)>>) {
+        for frame in frames {
+            if self.lost() {
+                continue;
+            }
+            for (at, byte) in frame {
+                self.receive(at, byte);
+            }
+        }
+    }
+
+    /// Put a byte on the servant to master line, garbling any byte overlapping in time
+    fn receive(&mut self, at: Instant, byte: u8) {
+        let i = self.rx.partition_point(|(t, _)| *t < at);
+        let overlaps = |t: Instant| {
+            let d = if t > at { t - at } else { at - t };
+            d < self.byte_time
+        };
+        for j in [i.wrapping_sub(1), i] {
+            if let Some((t, b)) = self.rx.get_mut(j) {
+                if overlaps(*t) {
+                    // garbled, even for equal bytes (the servants' clocks are never in
+                    // perfect sync), while keeping the delimiters of simultaneous frames
+                    *b = b.wrapping_add(byte);
+                    return;
+                }
+            }
+        }
+        self.rx.insert(i, (at, byte));
+    }
+}
+
+impl Write for SimPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.tx_idle.max(Instant::now());
+        for (i, byte) in buf.iter().enumerate() {
+            if let Some(frame) = self.acc.push::<Request>(*byte) {
+                let arrival = start + self.transmit_time(i + 1);
+                self.deliver(frame, arrival);
+            }
+        }
+        self.tx_idle = start + self.transmit_time(buf.len());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for SimPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let now = loop {
+            let now = Instant::now();
+            self.tick(now);
+            match self.rx.front() {
+                Some((at, _)) if *at <= now => break now,
+                _ if now >= deadline => return Err(ErrorKind::TimedOut.into()),
+                front => {
+                    // wake up for the next byte, the deadline or the next tick
+                    let wake = front.map_or(deadline, |(at, _)| deadline.min(*at));
+                    sleep(wake.min(now + TICK) - now);
+                }
+            }
+        };
+        let mut n = 0;
+        while n < buf.len() {
+            match self.rx.front() {
+                Some((at, byte)) if *at <= now => {
+                    buf[n] = *byte;
+                    n += 1;
+                    self.rx.pop_front();
+                }
+                _ => break,
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Port for SimPort {
+    fn set_read_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Time synchronisation of servants to the master clock
+//!
+//! `sync_time` runs `SYNC_ROUNDS` NTP-style exchanges (`Command::TimeSync`) and keeps the
+//! one with the shortest round trip, the least affected by queuing and retransmission.
+//! The offset is then sent to the servant (`Command::ClockOffset`), see `Clock`.
+//!
+//! The offset assumes symmetric delays, requests and replies of different length bias it
+//! by half the difference of their transmission times.
+
+use crate::{Error, Master, Port, Retry};
+use master_and_servant::{Command, DevId, Micros, Response};
+use std::time::Duration;
+
+/// Number of exchanges by `Master::sync_time`
+pub const SYNC_ROUNDS: usize = 4;
+
+/// Outcome of `Master::sync_time`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSync {
+    /// Servant time minus master time, in microseconds
+    pub offset: i64,
+    /// Round trip delay, servant processing excluded
+    pub delay: Duration,
+}
+
+impl ClockSync {
+    /// Offset and delay of an exchange sent at `t1`, received at `t2`, answered at `t3`
+    /// and its reply received at `t4`
+    fn measure(t1: Micros, t2: Micros, t3: Micros, t4: Micros) -> Self {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        Self {
+            offset: ((t2 - t1) + (t3 - t4)) / 2,
+            delay: Duration::from_micros(((t4 - t1) - (t3 - t2)).max(0) as u64),
+        }
+    }
+}
+
+impl<P: Port> Master<P> {
+    /// Master time in microseconds, since the master was created
+    pub fn now(&self) -> Micros {
+        self.epoch.elapsed().as_micros() as Micros
+    }
+
+    /// Measure the clock offset of `dev` and have it adopt the master time base
+    pub fn sync_time(&mut self, dev: DevId) -> Result<ClockSync, Error> {
+        // a retransmission would be answered with the timestamps of the first transmission
+        let retry = Retry {
+            retries: 0,
+            ..self.retry()
+        };
+        let mut best: Option<ClockSync> = None;
+        for _ in 0..SYNC_ROUNDS {
+            let t1 = self.now();
+            let resp = match self.request_with(Command::TimeSync { t1, dev }, retry) {
+                Ok(resp) => resp,
+                Err(Error::Timeout) => continue,
+                Err(err) => return Err(err),
+            };
+            let t4 = self.now();
+            let Response::TimeSync { t1: echo, t2, t3 } = resp else {
+                return Err(Error::Unexpected(resp));
+            };
+            if echo != t1 {
+                return Err(Error::Unexpected(resp));
+            }
+            let sync = ClockSync::measure(t1, t2, t3, t4);
+            if best.is_none_or(|best| sync.delay < best.delay) {
+                best = Some(sync);
+            }
+        }
+        let sync = best.ok_or(Error::Timeout)?;
+        match self.send_to(
+            dev,
+            Command::ClockOffset {
+                offset: sync.offset,
+                dev,
+            },
+        )? {
+            Response::SetOk => Ok(sync),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_and_delay() {
+        // servant 5 s ahead, 300 us each way, 100 us processing
+        let sync = ClockSync::measure(1_000, 5_001_300, 5_001_400, 1_700);
+        assert_eq!(sync.offset, 5_000_000);
+        assert_eq!(sync.delay, Duration::from_micros(600));
+        // servant behind
+        let sync = ClockSync::measure(5_000_000, 1_000_300, 1_000_400, 5_000_700);
+        assert_eq!(sync.offset, -4_000_000);
+        assert_eq!(sync.delay, Duration::from_micros(600));
+    }
+
+    #[test]
+    fn asymmetric_delays_bias_the_offset() {
+        // 100 us there, 500 us back: off by half the difference
+        let sync = ClockSync::measure(0, 100, 100, 600);
+        assert_eq!(sync.offset, -200);
+        assert_eq!(sync.delay, Duration::from_micros(600));
+    }
+
+    #[test]
+    fn negative_delay_is_clamped() {
+        // servant processing reported longer than the round trip
+        let sync = ClockSync::measure(0, 100, 1_000, 500);
+        assert_eq!(sync.delay, Duration::ZERO);
+    }
+}
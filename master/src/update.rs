@@ -0,0 +1,98 @@
+//! Firmware upload
+//!
+//! `update` announces the image (`Command::UpdateBegin`), sends it chunk by chunk,
+//! then has the servant verify and commit it, see `Updater`. If the link is lost (or
+//! a chunk goes missing) the sequence is repeated, resuming the upload from where the
+//! servant stands, giving up after `RESUMES` attempts in a row without progress. An
+//! interrupted upload of the same image also resumes where it stopped.
+
+use crate::{Error, Master, Port};
+use master_and_servant::{digest, Command, DevId, ErrorCode, Response, CHUNK, CKSUM};
+
+/// Number of times in a row `Master::update` resumes an upload without progress
+pub const RESUMES: u32 = 3;
+
+impl<P: Port> Master<P> {
+    /// Upload `image` to `dev`, verify and commit it
+    ///
+    /// `progress` is called with the number of bytes acknowledged and the image size.
+    pub fn update(
+        &mut self,
+        dev: DevId,
+        image: &[u8],
+        mut progress: impl FnMut(u32, u32),
+    ) -> Result<(), Error> {
+        let mut acked = 0;
+        let mut resumes = 0;
+        loop {
+            let before = acked;
+            let result = self.upload(dev, image, &mut acked, &mut progress);
+            if acked > before {
+                resumes = 0;
+            }
+            match result {
+                Ok(()) => return Ok(()),
+                Err(Error::Timeout)
+                | Err(Error::Servant {
+                    code: ErrorCode::OutOfOrder,
+                    ..
+                }) if resumes < RESUMES => resumes += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Abandon the update of `dev`, which fails once committed
+    pub fn abort_update(&mut self, dev: DevId) -> Result<(), Error> {
+        match self.send_to(dev, Command::UpdateAbort(dev))? {
+            Response::SetOk => Ok(()),
+            resp => Err(Error::Unexpected(resp)),
+        }
+    }
+
+    /// Send the chunks not yet received by `dev`, then verify and commit the image
+    fn upload(
+        &mut self,
+        dev: DevId,
+        image: &[u8],
+        acked: &mut u32,
+        progress: &mut impl FnMut(u32, u32),
+    ) -> Result<(), Error> {
+        // rejected by the servant if too large
+        let size = image.len().try_into().unwrap_or(u32::MAX);
+        let begin = Command::UpdateBegin {
+            size,
+            digest: digest(image),
+            dev,
+        };
+        *acked = match self.send_to(dev, begin)? {
+            Response::UpdateReady { received } if received <= size => received,
+            resp => return Err(Error::Unexpected(resp)),
+        };
+        progress(*acked, size);
+        // `acked` is a multiple of `CHUNK` unless complete
+        let first = (*acked as usize).div_ceil(CHUNK);
+        for (index, data) in image.chunks(CHUNK).enumerate().skip(first) {
+            let cmd = Command::UpdateChunk {
+                index: index as u32,
+                data: data.iter().copied().collect(),
+                crc: CKSUM.checksum(data),
+                dev,
+            };
+            match self.send_to(dev, cmd)? {
+                Response::SetOk => {
+                    *acked = (index * CHUNK + data.len()) as u32;
+                    progress(*acked, size);
+                }
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        for cmd in [Command::UpdateVerify(dev), Command::UpdateCommit(dev)] {
+            match self.send_to(dev, cmd)? {
+                Response::SetOk => {}
+                resp => return Err(Error::Unexpected(resp)),
+            }
+        }
+        Ok(())
+    }
+}
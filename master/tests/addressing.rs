@@ -0,0 +1,66 @@
+//! Unicast and multicast addressing of simulated servants with overlapping `DevId`s
+
+use master::sim::{SimPort, SimServant};
+use master::{Discovered, Master, Retry};
+use master_and_servant::{Command, DevId, Response, Value};
+use std::time::Duration;
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+
+/// Servants 1 and 2 (single bit) and 3 (bits of both)
+fn master() -> Master<SimPort> {
+    let servants = [1, 2, 3].into_iter().map(SimServant::new).collect();
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(100),
+        retries: 1,
+        backoff: 1,
+    });
+    master
+}
+
+#[test]
+fn unicast_to_multi_bit_dev_id() {
+    let mut master = master();
+    assert_eq!(
+        master
+            .send_to(3, Command::Set(7, Value::U32(3), 3))
+            .unwrap(),
+        Response::SetOk
+    );
+    assert_eq!(master.get::<u32>(3, 7, 0).unwrap(), 3);
+    for dev in [1, 2] {
+        master.set(dev, 7, dev).unwrap();
+    }
+    let executed: Vec<usize> = master.port().servants.iter().map(|s| s.executed).collect();
+    assert_eq!(executed, [1, 1, 2]);
+}
+
+#[test]
+fn scan_without_false_collisions() {
+    let mut master = master();
+    let found: Vec<DevId> = master
+        .scan(1..=3)
+        .unwrap()
+        .into_iter()
+        .map(|discovered| match discovered {
+            Discovered::Servant(dev, _) => dev,
+            Discovered::Collision(dev) => panic!("collision at {}", dev),
+        })
+        .collect();
+    assert_eq!(found, [1, 2, 3]);
+}
+
+#[test]
+fn multicast_only_by_mask() {
+    let mut master = master();
+    let multicast = master
+        .multicast(0b011, Command::Set(7, Value::U32(9), 0))
+        .unwrap();
+    let devs: Vec<DevId> = multicast.responses.iter().map(|(dev, _)| *dev).collect();
+    assert_eq!(devs, [1, 2]);
+    assert!(multicast.silent.is_empty());
+    // servant 3 takes no part in multicast
+    assert_eq!(master.port().servants[2].executed, 0);
+}
@@ -0,0 +1,129 @@
+//! Address assignment of simulated servants by unique hardware id arbitration
+
+mod common;
+
+use common::Scripted;
+use master::sim::{SimPort, SimServant};
+use master::{Master, Retry};
+use master_and_servant::{Command, DevId, Reply, Request, Response, Uid, UNASSIGNED};
+use std::time::Duration;
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+
+fn master(servants: Vec<SimServant>) -> Master<SimPort> {
+    let mut master = Master::new(SimPort::new(BAUD, TURNAROUND, servants));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(60),
+        retries: 1,
+        backoff: 1,
+    });
+    master
+}
+
+fn unassigned(uid: u128) -> SimServant {
+    SimServant::new(UNASSIGNED).with_uid(Uid::from_u128(uid))
+}
+
+/// The `DevId` of each servant
+fn devs(master: &mut Master<SimPort>) -> Vec<DevId> {
+    master.port().servants.iter().map(|s| s.dev()).collect()
+}
+
+#[test]
+fn colliding_prefixes() {
+    // pairwise sharing prefixes of 1, 4 and 12 bits
+    let uids = [
+        0x8a50_0000_0000_0000_0000_0000_0000_0000,
+        0x0a50_0000_0000_0000_0000_0000_0000_0000,
+        0x8a58_0000_0000_0000_0000_0000_0000_0000,
+        0x0000_0000_0000_0000_0000_0000_0000_0001,
+        0x8000_0000_0000_0000_0000_0000_0000_0000,
+    ];
+    let mut master = master(uids.into_iter().map(unassigned).collect());
+    let assigned = master.assign(10..).unwrap();
+
+    // depth first, the `0` half first, so in the order of the `Uid`s
+    let mut sorted = uids;
+    sorted.sort();
+    let expected: Vec<(Uid, DevId)> = sorted
+        .into_iter()
+        .zip(10..)
+        .map(|(uid, dev)| (Uid::from_u128(uid), dev))
+        .collect();
+    assert_eq!(assigned, expected);
+    for (servant, uid) in master.port().servants.iter().zip(uids) {
+        let (_, dev) = expected
+            .iter()
+            .find(|(u, _)| *u == Uid::from_u128(uid))
+            .unwrap();
+        assert_eq!(servant.dev(), *dev);
+    }
+}
+
+#[test]
+fn already_assigned_servants_keep_their_dev_id() {
+    let servants = vec![
+        unassigned(0x4000_0000_0000_0000_0000_0000_0000_0000),
+        // assigned earlier, or flashed with a fixed `DevId`
+        SimServant::new(3).with_uid(Uid::from_u128(0x4000_0000_0000_0000_0000_0000_0000_0001)),
+        SimServant::new(4),
+        unassigned(0xc000_0000_0000_0000_0000_0000_0000_0000),
+    ];
+    let mut master = master(servants);
+    let assigned = master.assign([5, 6, 7]).unwrap();
+    assert_eq!(
+        assigned,
+        [
+            (Uid::from_u128(0x4000_0000_0000_0000_0000_0000_0000_0000), 5),
+            (Uid::from_u128(0xc000_0000_0000_0000_0000_0000_0000_0000), 6),
+        ]
+    );
+    assert_eq!(devs(&mut master), [5, 3, 4, 6]);
+
+    // nothing left to assign
+    assert_eq!(master.assign([7, 8]).unwrap(), []);
+    assert_eq!(devs(&mut master), [5, 3, 4, 6]);
+}
+
+#[test]
+fn pool_exhausted() {
+    let servants = [0x1u128, 0x2, 0x3]
+        .into_iter()
+        .map(|uid| unassigned(uid << 120))
+        .collect();
+    let mut master = master(servants);
+    let assigned = master.assign([8]).unwrap();
+    assert_eq!(assigned, [(Uid::from_u128(0x1 << 120), 8)]);
+    assert_eq!(devs(&mut master), [8, UNASSIGNED, UNASSIGNED]);
+}
+
+#[test]
+fn unacknowledged_assignment_is_skipped() {
+    let uids = [0x1u128 << 120, 0x2 << 120, 0x3 << 120].map(Uid::from_u128);
+    let mut master = Master::new(Scripted::new(move |request: &Request| {
+        let reply = |resp| Reply {
+            seq: request.seq,
+            dev: request.cmd.dev(),
+            resp,
+        };
+        match request.cmd {
+            Command::Claim(prefix, len, _) => uids
+                .iter()
+                .filter(|uid| uid.has_prefix(prefix, len))
+                .map(|uid| reply(Response::Claim(*uid)))
+                .collect(),
+            // the assignment of the second servant is never acknowledged
+            Command::Assign(uid, _, _) if uid == uids[1] => Vec::new(),
+            Command::Assign(..) => vec![reply(Response::SetOk)],
+            _ => Vec::new(),
+        }
+    }));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(10),
+        retries: 1,
+        backoff: 1,
+    });
+    let assigned = master.assign(10..).unwrap();
+    assert_eq!(assigned, [(uids[0], 10), (uids[2], 12)]);
+}
@@ -0,0 +1,70 @@
+//! Batches of operations on simulated servants, with and without `Capabilities::BATCH`
+
+use master::sim::{SimPort, SimServant};
+use master::Master;
+use master_and_servant::{Access, Capabilities, ErrorCode, Op, Outcome, Slot, Value, BATCH};
+use std::time::Duration;
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const DEV: u32 = 0b001;
+
+fn master(servant: SimServant) -> Master<SimPort> {
+    Master::new(SimPort::new(BAUD, TURNAROUND, vec![servant]))
+}
+
+/// Operations failing in the middle of a batch, `0x11` is read-only
+fn ops() -> [Op; 4] {
+    [
+        Op::Set(0x10, Value::U32(1)),
+        Op::Set(0x11, Value::U32(2)),
+        Op::Get(0x10, 1),
+        Op::Get(0x10, 0),
+    ]
+}
+
+fn expected() -> [Outcome; 4] {
+    [
+        Outcome::SetOk,
+        Outcome::Error(ErrorCode::ReadOnly, 0x11),
+        Outcome::Error(ErrorCode::UnknownParameter, 1),
+        Outcome::Data(0x10, 0, Value::U32(1)),
+    ]
+}
+
+fn servant() -> SimServant {
+    SimServant::new(DEV).with_parameter(Slot::new(0x11, 0, Access::ReadOnly, Value::U32(0)))
+}
+
+#[test]
+fn partial_failure() {
+    let mut master = master(servant());
+    assert_eq!(master.batch(DEV, &ops()).unwrap(), expected());
+    assert_eq!(master.port().servants[0].executed, 1);
+}
+
+#[test]
+fn split_at_capacity() {
+    let mut master = master(SimServant::new(DEV));
+    let n = 2 * BATCH as u32 + 1;
+    let sets: Vec<Op> = (0..n).map(|i| Op::Set(i, Value::U32(i))).collect();
+    assert!(master
+        .batch(DEV, &sets)
+        .unwrap()
+        .iter()
+        .all(|o| *o == Outcome::SetOk));
+    let gets: Vec<Op> = (0..n).map(|i| Op::Get(i, 0)).collect();
+    let outcomes = master.batch(DEV, &gets).unwrap();
+    let values: Vec<Outcome> = (0..n).map(|i| Outcome::Data(i, 0, Value::U32(i))).collect();
+    assert_eq!(outcomes, values);
+    assert_eq!(master.port().servants[0].executed, 6);
+}
+
+#[test]
+fn single_requests_without_batch_capability() {
+    let mut master = master(servant().with_capabilities(Capabilities::TELEMETRY));
+    master.hello(DEV).unwrap();
+    let before = master.port().servants[0].executed;
+    assert_eq!(master.batch(DEV, &ops()).unwrap(), expected());
+    assert_eq!(master.port().servants[0].executed - before, ops().len());
+}
@@ -0,0 +1,65 @@
+//! A port scripted by the test
+
+use master::Port;
+use master_and_servant::{serialize_crc_cobs, Frame, FrameAccumulator, Reply, Request, WireSize};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::Duration;
+
+/// A port answering each request by the replies `respond` makes up, at once
+pub struct Scripted<F> {
+    respond: F,
+    acc: FrameAccumulator<{ Request::MAX_FRAME_SIZE }>,
+    rx: VecDeque<u8>,
+    /// The requests received
+    pub requests: Vec<Request>,
+}
+
+impl<F: FnMut(&Request) -> Vec<Reply>> Scripted<F> {
+    pub fn new(respond: F) -> Self {
+        Self {
+            respond,
+            acc: FrameAccumulator::new(),
+            rx: VecDeque::new(),
+            requests: Vec::new(),
+        }
+    }
+}
+
+impl<F: FnMut(&Request) -> Vec<Reply>> Write for Scripted<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for byte in buf {
+            if let Some(Ok(request)) = self.acc.push::<Request>(*byte) {
+                let mut out_buf = Reply::frame_buf();
+                for reply in (self.respond)(&request) {
+                    let frame = serialize_crc_cobs(&reply, &mut out_buf).unwrap();
+                    self.rx.extend(frame.iter());
+                }
+                self.requests.push(request);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> Read for Scripted<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.rx.pop_front() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Err(ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+impl<F: FnMut(&Request) -> Vec<Reply>> Port for Scripted<F> {
+    fn set_read_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+}
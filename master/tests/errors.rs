@@ -0,0 +1,60 @@
+//! Errors reported by a servant map to `Error::Servant`
+
+mod common;
+
+use common::Scripted;
+use master::{Error, Master};
+use master_and_servant::{
+    Access, Command, ErrorCode, ParameterRegistry, Reply, Request, Servant, Slot, Value,
+};
+use std::time::Duration;
+
+const DEV: u32 = 0b001;
+
+/// A servant serving parameters only, with `Value::U32` parameter 0x12
+fn master() -> Master<impl master::Port> {
+    let mut servant: Servant<4> = Servant::new(DEV);
+    let mut registry =
+        ParameterRegistry::new([Slot::new(0x12, 0, Access::ReadOnly, Value::U32(7))]);
+    Master::new(Scripted::new(move |request: &Request| -> Vec<Reply> {
+        servant
+            .handle(Ok(request.clone()), |cmd| registry.dispatch(cmd))
+            .map(|out| out.reply)
+            .into_iter()
+            .collect()
+    }))
+}
+
+fn assert_servant_error(result: Result<impl std::fmt::Debug, Error>, expected: ErrorCode) {
+    match result {
+        Err(Error::Servant { code, .. }) if code == expected => {}
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
+
+#[test]
+fn rejected_requests() {
+    let mut master = master();
+    assert_servant_error(master.get::<u32>(DEV, 0x13, 0), ErrorCode::UnknownId);
+    assert_servant_error(master.set(DEV, 0x12, 1u32), ErrorCode::ReadOnly);
+    assert_eq!(master.get::<u32>(DEV, 0x12, 0).unwrap(), 7);
+}
+
+#[test]
+fn unhandled_command_is_unsupported() {
+    let mut master = master();
+    // not served by the registry, answered by its fallback
+    let subscribe = master.subscribe(DEV, 0x12, 0, Duration::from_millis(100), 1.0);
+    assert_servant_error(subscribe, ErrorCode::Unsupported);
+    let unsubscribe = Command::Unsubscribe {
+        id: 0x12,
+        parameter: 0,
+        dev: DEV,
+    };
+    let mut results = master
+        .pipeline(&[Command::Get(0x12, 0, DEV), unsubscribe], 2)
+        .unwrap()
+        .into_iter();
+    assert!(results.next().unwrap().is_ok());
+    assert_servant_error(results.next().unwrap(), ErrorCode::Unsupported);
+}
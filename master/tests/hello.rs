@@ -0,0 +1,124 @@
+//! Protocol handshake with servants of other builds
+
+mod common;
+
+use common::Scripted;
+use master::{Error, Master, MASTER_CAPABILITIES};
+use master_and_servant::{
+    Capabilities, Command, Hello, Op, Outcome, Reply, Request, Response, Value, WireSize, PROTOCOL,
+    SCHEMA,
+};
+use std::time::Duration;
+
+const DEV: u32 = 0b001;
+
+/// A servant answering `Hello` by `theirs`, `Get` by 7 and `Set` by `SetOk`
+fn master(theirs: Hello) -> Master<Scripted<impl FnMut(&Request) -> Vec<Reply>>> {
+    Master::new(Scripted::new(move |request: &Request| {
+        let resp = match request.cmd {
+            Command::Hello(..) => Response::Hello(theirs),
+            Command::Get(id, parameter, dev) => Response::Data(id, parameter, Value::U32(7), dev),
+            Command::Set(..) => Response::SetOk,
+            _ => return Vec::new(),
+        };
+        vec![Reply {
+            seq: request.seq,
+            dev: request.cmd.dev(),
+            resp,
+        }]
+    }))
+}
+
+fn capable() -> Hello {
+    Hello::new(Request::MAX_FRAME_SIZE as u16, MASTER_CAPABILITIES)
+}
+
+fn refused(theirs: Hello) {
+    let mut master = master(theirs);
+    let result = master.hello(DEV);
+    assert!(
+        matches!(result, Err(Error::Incompatible(hello)) if hello == theirs),
+        "{:?}",
+        result
+    );
+    assert_eq!(master.peer(DEV), None);
+}
+
+#[test]
+fn compatible() {
+    let mut master = master(capable());
+    assert_eq!(master.hello(DEV).unwrap(), capable());
+    assert_eq!(master.peer(DEV), Some(capable()));
+    assert!(master.supports(DEV, MASTER_CAPABILITIES));
+}
+
+#[test]
+fn other_protocol_or_schema() {
+    refused(Hello {
+        protocol: PROTOCOL + 1,
+        ..capable()
+    });
+    refused(Hello {
+        schema: SCHEMA ^ 1,
+        ..capable()
+    });
+}
+
+#[test]
+fn frames_too_small_for_requests() {
+    refused(Hello {
+        max_frame: Request::MAX_FRAME_SIZE as u16 - 1,
+        ..capable()
+    });
+}
+
+#[test]
+fn downgraded() {
+    let theirs = Hello {
+        capabilities: Capabilities::TELEMETRY,
+        ..capable()
+    };
+    let mut master = master(theirs);
+    master.hello(DEV).unwrap();
+    assert!(master.supports(DEV, Capabilities::TELEMETRY));
+    assert!(!master.supports(DEV, Capabilities::BATCH));
+
+    // one request per operation
+    let ops = [Op::Set(0x12, Value::U32(7)), Op::Get(0x12, 0)];
+    let outcomes = master.batch(DEV, &ops).unwrap();
+    assert_eq!(
+        outcomes,
+        [Outcome::SetOk, Outcome::Data(0x12, 0, Value::U32(7))]
+    );
+    let requests = &master.port().requests;
+    assert!(matches!(requests[1].cmd, Command::Set(..)));
+    assert!(matches!(requests[2].cmd, Command::Get(..)));
+
+    // refused without asking the servant
+    let sent = master.port().requests.len();
+    let mut blob = Vec::new();
+    assert!(matches!(
+        master.read_blob(DEV, 0, &mut blob),
+        Err(Error::Unsupported(Capabilities::BULK))
+    ));
+    assert!(matches!(
+        master.send_message(DEV, b"hi", &mut [0; 8]),
+        Err(Error::Unsupported(Capabilities::BULK))
+    ));
+    assert_eq!(master.port().requests.len(), sent);
+    // still supported, but not answered here
+    let result = master.subscribe(DEV, 0x12, 0, Duration::from_millis(100), 1.0);
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+}
+
+#[test]
+fn refused_servant_is_not_downgraded() {
+    let mut master = master(Hello {
+        protocol: PROTOCOL + 1,
+        capabilities: Capabilities::TELEMETRY,
+        ..capable()
+    });
+    assert!(master.hello(DEV).is_err());
+    // never greeted successfully, assumed capable
+    assert!(master.supports(DEV, Capabilities::BATCH));
+}
@@ -0,0 +1,64 @@
+//! Throughput of windowed requests against a simulated servant
+
+use master::sim::{SimPort, SimServant};
+use master::{Master, Retry};
+use master_and_servant::{Command, Value};
+use std::time::{Duration, Instant};
+
+const BAUD: u32 = 9600;
+const TURNAROUND: Duration = Duration::from_millis(5);
+const N: u32 = 24;
+
+/// Requests per second for `window`, all requests must succeed
+fn throughput(window: usize, loss: f64) -> f64 {
+    let cmds: Vec<Command> = (0..N)
+        .map(|i| match i % 2 {
+            0 => Command::Set(i, Value::U32(i), 0b001),
+            _ => Command::Get(i - 1, 0, 0b001),
+        })
+        .collect();
+    let port = SimPort::new(BAUD, TURNAROUND, vec![SimServant::new(0b001)]).with_loss(loss);
+    let mut master = Master::new(port);
+    master.set_baud(BAUD);
+    // the timeout covers a single exchange, not a full window
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(100),
+        ..Retry::default()
+    });
+
+    let start = Instant::now();
+    let responses = master.pipeline(&cmds, window).unwrap();
+    let elapsed = start.elapsed();
+    assert!(responses.iter().all(|r| r.is_ok()), "window {}", window);
+    assert_eq!(master.port().servants[0].executed, N as usize);
+    N as f64 / elapsed.as_secs_f64()
+}
+
+#[test]
+fn throughput_grows_with_window() {
+    let mut last = 0.0;
+    for window in [1, 2, 4, 8] {
+        let rate = throughput(window, 0.0);
+        // once the link is saturated the rate levels off, allow for timing jitter
+        assert!(
+            rate > 0.9 * last,
+            "window {}: {:.1} requests/s, {:.1} before",
+            window,
+            rate,
+            last
+        );
+        last = last.max(rate);
+    }
+}
+
+#[test]
+fn window_beats_stop_and_wait_under_loss() {
+    let stop_and_wait = throughput(1, 0.02);
+    let windowed = throughput(8, 0.02);
+    assert!(
+        windowed > 1.5 * stop_and_wait,
+        "{:.1} vs {:.1} requests/s",
+        windowed,
+        stop_and_wait
+    );
+}
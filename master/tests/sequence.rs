@@ -0,0 +1,101 @@
+//! Replies are matched to requests by sequence number and servant
+
+mod common;
+
+use common::Scripted;
+use master::{Error, Master, Retry};
+use master_and_servant::{Command, Reply, Request, Response, Value};
+use std::time::Duration;
+
+fn master<F: FnMut(&Request) -> Vec<Reply>>(respond: F) -> Master<Scripted<F>> {
+    let mut master = Master::new(Scripted::new(respond));
+    master.set_retry(Retry {
+        timeout: Duration::from_millis(10),
+        retries: 2,
+        backoff: 1,
+    });
+    master
+}
+
+fn data(value: u32) -> Response {
+    Response::Data(7, 0, Value::U32(value), 1)
+}
+
+#[test]
+fn stale_and_mismatched_replies_are_discarded() {
+    let mut master = master(|request| {
+        let seq = request.seq;
+        vec![
+            // late reply to the previous request
+            Reply {
+                seq: seq.wrapping_sub(1),
+                dev: 1,
+                resp: data(1),
+            },
+            // another servant
+            Reply {
+                seq,
+                dev: 2,
+                resp: data(2),
+            },
+            Reply {
+                seq,
+                dev: 1,
+                resp: data(3),
+            },
+        ]
+    });
+    assert_eq!(master.get::<u32>(1, 7, 0).unwrap(), 3);
+    assert_eq!(master.port().requests.len(), 1);
+}
+
+#[test]
+fn only_stale_replies_time_out() {
+    let mut master = master(|request| {
+        vec![Reply {
+            seq: request.seq.wrapping_add(1),
+            dev: 1,
+            resp: data(1),
+        }]
+    });
+    let result = master.send_to(1, Command::Get(7, 0, 1));
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+    // retransmitted with the same sequence number
+    let requests = &master.port().requests;
+    assert_eq!(requests.len(), 3);
+    assert!(requests.iter().all(|r| r.seq == requests[0].seq));
+}
+
+#[test]
+fn late_reply_not_taken_for_the_next_request() {
+    let mut first = true;
+    let mut previous = None;
+    let mut master = master(move |request| {
+        if first {
+            // lost reply, delivered with the next request
+            first = false;
+            previous = Some(request.seq);
+            return Vec::new();
+        }
+        let mut replies = Vec::new();
+        if let Some(seq) = previous.take() {
+            replies.push(Reply {
+                seq,
+                dev: 1,
+                resp: data(1),
+            });
+        }
+        replies.push(Reply {
+            seq: request.seq,
+            dev: 1,
+            resp: data(2),
+        });
+        replies
+    });
+    master.set_retry(Retry {
+        retries: 0,
+        ..master.retry()
+    });
+    assert!(matches!(master.get::<u32>(1, 7, 0), Err(Error::Timeout)));
+    assert_eq!(master.get::<u32>(1, 7, 0).unwrap(), 2);
+}
@@ -0,0 +1,91 @@
+//! Timeouts run from the expected end of transmission, misses degrade watched links
+
+use master::sim::{SimPort, SimServant};
+use master::{Error, Keepalive, LinkState, Master, Retry};
+use master_and_servant::{Command, DevId, ErrorCode, Response, CHUNK, CKSUM};
+use std::time::Duration;
+
+const TURNAROUND: Duration = Duration::from_millis(5);
+
+fn master(baud: u32, servants: &[DevId], timeout: Duration) -> Master<SimPort> {
+    let servants = servants.iter().copied().map(SimServant::new).collect();
+    let mut master = Master::new(SimPort::new(baud, TURNAROUND, servants));
+    master.set_baud(baud);
+    master.set_retry(Retry {
+        timeout,
+        retries: 0,
+        backoff: 1,
+    });
+    master
+}
+
+/// A request frame of about 50 bytes, rejected by a servant not updating by a short reply
+fn chunk(dev: DevId) -> Command {
+    let data = [0x55; CHUNK];
+    Command::UpdateChunk {
+        index: 0,
+        data: data.iter().copied().collect(),
+        crc: CKSUM.checksum(&data),
+        dev,
+    }
+}
+
+#[test]
+fn long_frames_at_low_baud() {
+    // the request takes about 400 ms to send, its reply about 100 ms
+    let mut master = master(1200, &[0b001], Duration::from_millis(200));
+    let result = master.request(chunk(0b001));
+    assert!(
+        matches!(
+            result,
+            Err(Error::Servant {
+                code: ErrorCode::InvalidState,
+                ..
+            })
+        ),
+        "{:?}",
+        result
+    );
+
+    let multicast = master.multicast(0b001, chunk(0)).unwrap();
+    assert!(multicast.silent.is_empty());
+    assert!(matches!(
+        multicast.responses[..],
+        [(0b001, Response::Error(ErrorCode::InvalidState, _))]
+    ));
+
+    let responses = master.pipeline(&[chunk(0b001), chunk(0b001)], 2).unwrap();
+    assert!(responses.iter().all(|r| matches!(
+        r,
+        Err(Error::Servant {
+            code: ErrorCode::InvalidState,
+            ..
+        })
+    )));
+}
+
+#[test]
+fn misses_degrade_watched_links() {
+    let mut master = master(9600, &[0b001, 0b010], Duration::from_millis(50));
+    master.set_keepalive(Keepalive {
+        down: 3,
+        ..Keepalive::default()
+    });
+    for dev in [0b001, 0b010] {
+        master.watch(dev);
+        master.heartbeat(dev).unwrap();
+        assert_eq!(master.link_state(dev), Some(LinkState::Up));
+    }
+    master.port().servants[1].power_off();
+
+    let multicast = master.multicast(0b011, Command::Heartbeat(0)).unwrap();
+    assert_eq!(multicast.silent, [0b010]);
+    assert_eq!(master.link_state(0b010), Some(LinkState::Degraded));
+    assert_eq!(master.link_state(0b001), Some(LinkState::Up));
+
+    // probed by the scan
+    master.scan(0b010..=0b010).unwrap();
+    let responses = master.pipeline(&[Command::Heartbeat(0b010)], 1).unwrap();
+    assert!(matches!(responses[..], [Err(Error::Timeout)]));
+    assert_eq!(master.link_state(0b010), Some(LinkState::Down));
+}
@@ -0,0 +1,69 @@
+//! Typed values written to and read back from a simulated servant
+
+use master::sim::{SimPort, SimServant};
+use master::{Error, Master};
+use master_and_servant::{Access, ErrorCode, Fixed, Slot, Value};
+use std::time::Duration;
+
+const BAUD: u32 = 115_200;
+const TURNAROUND: Duration = Duration::from_millis(1);
+const DEV: u32 = 0b001;
+
+/// A parameter of each type, 0x10 and up
+fn master() -> Master<SimPort> {
+    let values = [
+        Value::Bool(false),
+        Value::I32(0),
+        Value::U32(0),
+        Value::F32(0.0),
+        Value::Fixed(Fixed::new(0, 8)),
+    ];
+    let servant = values
+        .iter()
+        .zip(0x10..)
+        .fold(SimServant::new(DEV), |s, (v, id)| {
+            s.with_parameter(Slot::new(id, 0, Access::ReadWrite, *v))
+        });
+    Master::new(SimPort::new(BAUD, TURNAROUND, vec![servant]))
+}
+
+#[test]
+fn every_variant_round_trips() {
+    let mut master = master();
+    master.set(DEV, 0x10, true).unwrap();
+    master.set(DEV, 0x11, -5i32).unwrap();
+    master.set(DEV, 0x12, u32::MAX).unwrap();
+    master.set(DEV, 0x13, 0.25f32).unwrap();
+    master.set(DEV, 0x14, Fixed::from_f32(1.5, 8)).unwrap();
+    assert!(master.get::<bool>(DEV, 0x10, 0).unwrap());
+    assert_eq!(master.get::<i32>(DEV, 0x11, 0).unwrap(), -5);
+    assert_eq!(master.get::<u32>(DEV, 0x12, 0).unwrap(), u32::MAX);
+    assert_eq!(master.get::<f32>(DEV, 0x13, 0).unwrap(), 0.25);
+    assert_eq!(master.get::<Fixed>(DEV, 0x14, 0).unwrap().to_f32(), 1.5);
+}
+
+#[test]
+fn mismatch() {
+    let mut master = master();
+    // rejected by the servant
+    let result = master.set(DEV, 0x12, 1.0f32);
+    assert!(
+        matches!(
+            result,
+            Err(Error::Servant {
+                code: ErrorCode::TypeMismatch,
+                detail: 0x12
+            })
+        ),
+        "{:?}",
+        result
+    );
+    assert_eq!(master.get::<u32>(DEV, 0x12, 0).unwrap(), 0);
+    // detected by the master
+    let result = master.get::<f32>(DEV, 0x12, 0);
+    assert!(
+        matches!(result, Err(Error::TypeMismatch(Value::U32(0)))),
+        "{:?}",
+        result
+    );
+}
@@ -11,4 +11,27 @@ fn main() {
 
     // Extend the linker search path
     println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    // The public key checking firmware updates (`cmd_crc_cobs_lib`), `UPDATE_KEY` or the
+    // one written by `cargo run -- keygen keys/dev.key` in `master`. Without it the
+    // examples are built refusing all updates, `update_key.rs` holds the key as an
+    // `Option<PublicKey>` expression.
+    println!("cargo:rerun-if-env-changed=UPDATE_KEY");
+    let key = env::var_os("UPDATE_KEY")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("../master/keys/dev.pub"));
+    println!("cargo:rerun-if-changed={}", key.display());
+    let built_in = match std::fs::read(&key) {
+        Ok(public) if public.len() == 32 => format!("Some({:?})", public),
+        _ => {
+            println!(
+                "cargo:warning=no public key at {}, firmware updates are refused (run \
+                 `cargo run -- keygen keys/dev.key` in `master`, or set UPDATE_KEY)",
+                key.display()
+            );
+            "None".into()
+        }
+    };
+    std::fs::write(out.join("update_key.rs"), built_in).unwrap();
 }
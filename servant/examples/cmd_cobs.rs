@@ -26,7 +26,7 @@ mod app {
     use hal::serial::uart::UartConfiguration;
     use hal::serial::usart::Event;
     use hal::serial::{usart::*, ExtBpsU32};
-    use master_and_servant::{Command, Response};
+    use master_and_servant::{Command, ErrorCode, Response, Value};
     use rtt_target::{rprintln, rtt_init_print};
 
     // Application dependencies
@@ -164,7 +164,9 @@ mod app {
 
                     let response = match cmd {
                         Command::Set(_id, _par, _dev) => Response::SetOk,
-                        Command::Get(id, par, dev) => Response::Data(id, par, 42, dev),
+                        Command::Get(id, par, dev) => Response::Data(id, par, Value::U32(42), dev),
+                        // not supported by this example
+                        _ => Response::Error(ErrorCode::Unsupported, 0),
                     };
 
                     rprintln!("response {:?}", response);
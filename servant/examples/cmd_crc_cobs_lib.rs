@@ -2,6 +2,21 @@
 //!
 //! ssmarshal + serde + crc + cobs
 //!
+//! Parameters subscribed to by the master are pushed as `Response::Notify`
+//! from the periodic `telemetry` task, timestamped by the `Clock` synchronised
+//! to the master. Scheduled and staged values are applied by the `actuate` task.
+//!
+//! Firmware updates are received into a RAM staging area, standing in for the second
+//! flash bank. Images must be signed with the key whose public key is built in (see
+//! `build.rs`), created in `master` by:
+//! cargo run -- keygen keys/dev.key
+//! cargo run -- sign <image> --key keys/dev.key --version 0.2.0 -o signed.bin
+//! cargo run -- update signed.bin
+//!
+//! A calibration table is exposed as a blob, read and written by `Master::read_blob`
+//! and `Master::write_blob`. Messages larger than a frame (`Master::send_message`) are
+//! echoed.
+//!
 //! Run on target: `cd servant`
 //! cargo embed --example cmd_crc_cobs_lib --release
 //!
@@ -20,9 +35,10 @@ mod app {
     use hal::clocks::*;
     use hal::efc::*;
     use hal::ehal::serial::{Read, Write};
-    use hal::fugit::RateExtU32;
+    use hal::fugit::{ExtU32, RateExtU32};
     use hal::generics::events::EventHandler;
     use hal::pio::*;
+    use hal::rtt::*;
     use hal::serial::uart::UartConfiguration;
     use hal::serial::{
         usart::{Event, Rx, Tx, Usart, Usart1},
@@ -31,25 +47,97 @@ mod app {
     use rtt_target::{rprint, rprintln, rtt_init_print};
 
     // Application dependencies
-    use core::mem::size_of;
-    use corncobs::{max_encoded_len, ZERO};
-    use master_and_servant::{deserialize_crc_cobs, serialize_crc_cobs, Command, Response};
+    use master_and_servant::{
+        encode_to_sink, Access, BlobId, BlobProvider, Blobs, Capabilities, Clock, DevId, ErrorCode,
+        FrameAccumulator, Messages, Micros, Op, Outgoing, ParameterRegistry, PublicKey, RamStaging,
+        Request, Schedule, Servant, Session, Slot, Subscriptions, UpdateState, Updater, Value,
+        Verifier, Version, WireSize, SLOT_BYTES,
+    };
     use nb::block;
 
-    const IN_SIZE: usize = max_encoded_len(size_of::<Command>() + size_of::<u32>());
-    const OUT_SIZE: usize = max_encoded_len(size_of::<Response>() + size_of::<u32>());
+    const IN_SIZE: usize = Request::MAX_FRAME_SIZE;
+    const DEV_ID: DevId = 0b001;
+    // receive window, allows the master to pipeline up to `WINDOW` requests
+    const WINDOW: usize = 4;
+    // reported in reply to `Command::Ping`
+    const VERSION: Version = Version {
+        major: 0,
+        minor: 1,
+        patch: 0,
+    };
+    // reported in reply to `Command::Hello`, the features dispatched by `lowprio`
+    const CAPABILITIES: Capabilities = {
+        let capabilities = Capabilities::BATCH
+            .union(Capabilities::TELEMETRY)
+            .union(Capabilities::BULK);
+        match UPDATE_KEY {
+            Some(_) => capabilities.union(Capabilities::AUTH),
+            None => capabilities,
+        }
+    };
+    // the parameters exposed to the master
+    const PARAMS: usize = 3;
+    const PARAMETERS: ParameterRegistry<PARAMS> = ParameterRegistry::new([
+        Slot::new(0x12, 0, Access::ReadWrite, Value::U32(42)),
+        Slot::new(0x13, 0, Access::ReadWrite, Value::F32(0.5)).on_write(unit_interval),
+        Slot::new(0x14, 0, Access::ReadOnly, Value::U32(0)).on_read(read_count),
+    ]);
+    // concurrent telemetry subscriptions
+    const SUBSCRIPTIONS: usize = 4;
+    // queued scheduled and staged values
+    const SCHEDULE: usize = 4;
+    // firmware staging area
+    const STAGING: usize = 64 * 1024;
+    // public key checking the signature of updates, provided by `build.rs`, without it
+    // updates are refused (answered `ErrorCode::Unsupported`)
+    const UPDATE_KEY: Option<PublicKey> = include!(concat!(env!("OUT_DIR"), "/update_key.rs"));
+    // the calibration table blob
+    const CALIBRATION_BLOB: BlobId = 0;
+    const CALIBRATION: usize = 256;
+    // largest message, and the time to wait for its next fragment
+    const MESSAGE: usize = 256;
+    const MESSAGE_TIMEOUT: Micros = 1_000_000;
+    // 12 MHz core clock, 9600 baud 8N1
+    const CYCLES_PER_BYTE: u32 = 12_000_000 * 10 / 9600;
+
+    #[monotonic(binds = RTT, default = true)]
+    type MyMon = Mono<8192>;
 
+    // shared by `lowprio` and `telemetry`, both at priority 1
     #[shared]
-    struct Shared {}
+    struct Shared {
+        #[lock_free]
+        servant: Servant<WINDOW>,
+        #[lock_free]
+        tx: Tx<Usart1>,
+        #[lock_free]
+        params: ParameterRegistry<PARAMS>,
+        #[lock_free]
+        subscriptions: Subscriptions<SUBSCRIPTIONS>,
+        #[lock_free]
+        clock: Clock,
+        #[lock_free]
+        schedule: Schedule<SCHEDULE>,
+        #[lock_free]
+        updater: Option<Updater<&'static mut RamStaging<STAGING>>>,
+        #[lock_free]
+        blobs: Blobs<Calibration>,
+        #[lock_free]
+        messages: Messages<&'static mut [u8; MESSAGE]>,
+    }
 
     #[local]
     struct Local {
-        tx: Tx<Usart1>,
         rx: Rx<Usart1>,
         usart: Usart<Usart1>,
     }
 
-    #[init()]
+    // buffers too large to be moved through the stack
+    #[init(local = [
+        staging: RamStaging<STAGING> = RamStaging::new(),
+        rx_message: [u8; MESSAGE] = [0; MESSAGE],
+        tx_message: [u8; MESSAGE] = [0; MESSAGE],
+    ])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
         rtt_init_print!();
         rprintln!("init");
@@ -96,7 +184,44 @@ mod app {
         usart.enter_mode(&uart);
         let (tx, rx) = uart.split();
 
-        (Shared {}, Local { tx, rx, usart }, init::Monotonics())
+        // own address, could be read from e.g. straps or flash
+        // reported by heartbeats, so the master detects resets
+        let session = session(&pac.GPBR, pac.TRNG);
+        let servant = Servant::new(DEV_ID)
+            .with_version(VERSION)
+            .with_capabilities(CAPABILITIES)
+            .with_session(session)
+            // the only servant on the uart
+            .with_error_replies();
+
+        let mono = Rtt::new_8192Hz(pac.RTT, &slck).into_monotonic();
+        telemetry::spawn().unwrap();
+        actuate::spawn().unwrap();
+
+        (
+            Shared {
+                servant,
+                tx,
+                params: PARAMETERS,
+                subscriptions: Subscriptions::new(),
+                clock: Clock::new(),
+                schedule: Schedule::new(),
+                updater: UPDATE_KEY.map(|key| {
+                    Updater::new(ctx.local.staging).with_verifier(Verifier::new(key, VERSION))
+                }),
+                blobs: Blobs::new(Calibration {
+                    table: [0; CALIBRATION],
+                    size: CALIBRATION as u32,
+                }),
+                messages: Messages::new(
+                    ctx.local.rx_message,
+                    ctx.local.tx_message,
+                    MESSAGE_TIMEOUT,
+                ),
+            },
+            Local { rx, usart },
+            init::Monotonics(mono),
+        )
     }
 
     #[task(binds=USART1, local = [rx, usart], priority = 2)]
@@ -127,52 +252,203 @@ mod app {
     #[task(
         priority = 1,
         capacity = 100,
-        local = [
+        shared = [
             tx,
+            servant,
+            params,
+            subscriptions,
+            clock,
+            schedule,
+            updater,
+            blobs,
+            messages,
+        ],
+        local = [
             // locally initialized resources
-            index: usize = 0,
-            in_buf: [u8; IN_SIZE] = [0u8; IN_SIZE],
-            out_buf: [u8; OUT_SIZE] = [0u8; OUT_SIZE]
+            acc: FrameAccumulator<IN_SIZE> = FrameAccumulator::new(),
         ]
     )]
     fn lowprio(ctx: lowprio::Context, data: u8) {
-        let lowprio::LocalResources {
+        let lowprio::SharedResources {
             tx,
-            index,
-            in_buf,
-            out_buf,
-        } = ctx.local;
-        rprint!("r{} {}", data, index);
-        in_buf[*index] = data;
-
-        // ensure index in range
-        if *index < IN_SIZE - 1 {
-            *index += 1;
-        }
+            servant,
+            params,
+            subscriptions,
+            clock,
+            schedule,
+            updater,
+            blobs,
+            messages,
+        } = ctx.shared;
+        let acc = ctx.local.acc;
+        rprint!("r{} ", data);
 
         // end of cobs frame
-        if data == ZERO {
-            rprintln!("\n-- cobs packet received {:?} --", &in_buf[0..*index]);
-            *index = 0;
-
-            match deserialize_crc_cobs::<Command>(in_buf) {
-                Ok(cmd) => {
-                    rprintln!("cmd {:?}", cmd);
-                    let response = match cmd {
-                        Command::Set(_id, _par, _dev) => Response::SetOk,
-                        Command::Get(id, par, dev) => Response::Data(id, par, 42, dev),
-                    };
-                    rprintln!("response {:?}", response);
-                    let to_write = serialize_crc_cobs(&response, out_buf);
-                    for byte in to_write {
-                        block!(tx.write(*byte)).unwrap();
-                    }
-                }
+        if let Some(frame) = acc.push::<Request>(data) {
+            let received = micros();
+            rprintln!("\n-- cobs packet received {:?} --", frame);
 
-                Err(err) => {
-                    rprintln!("ssmarshal err {:?}", err);
-                }
+            // retransmitted requests are answered without executing them again,
+            // requests addressed to other servants are ignored
+            let reply = servant.handle(frame, |cmd| {
+                clock
+                    .dispatch(cmd, received, micros())
+                    .or_else(|| subscriptions.dispatch(cmd, |id, par| params.check_read(id, par)))
+                    .or_else(|| {
+                        schedule.dispatch(cmd, clock.synced(received), |id, value| {
+                            params.check(id, value)
+                        })
+                    })
+                    .or_else(|| updater.as_mut().and_then(|u| u.dispatch(cmd)))
+                    .or_else(|| blobs.dispatch(cmd))
+                    .or_else(|| messages.dispatch(cmd, received, echo))
+                    .unwrap_or_else(|| params.dispatch(cmd))
+            });
+            if let Some(UpdateState::Committed { size, .. }) = updater.as_ref().map(Updater::state)
+            {
+                // a bootloader would swap the flash banks on reset
+                rprintln!("image of {} bytes committed", size);
+            }
+            // latch the values released by a trigger right away
+            apply(schedule, params, clock.now(micros()));
+            rprintln!("reply {:?}", reply);
+            // not addressed to us, or broadcast
+            let Some(Outgoing { reply, slot }) = reply else {
+                return;
+            };
+            // multicast replies are sent in our own time slot
+            cortex_m::asm::delay(slot * SLOT_BYTES as u32 * CYCLES_PER_BYTE);
+            // no output buffer, bytes are encoded as they go out
+            match encode_to_sink(&reply, |byte| block!(tx.write(byte))) {
+                Ok(n) => rprintln!("sent {}", n),
+                Err(err) => rprintln!("frame err {:?}", err),
+            }
+        }
+    }
+
+    // sends at most one due notification every 10 ms
+    #[task(priority = 1, shared = [tx, servant, params, subscriptions, clock])]
+    fn telemetry(ctx: telemetry::Context) {
+        let telemetry::SharedResources {
+            tx,
+            servant,
+            params,
+            subscriptions,
+            clock,
+        } = ctx.shared;
+        let now = clock.now(micros());
+        if let Some(resp) = subscriptions.poll(now, |id, par| params.sample(id, par)) {
+            let reply = servant.unsolicited(resp);
+            if let Err(err) = encode_to_sink(&reply, |byte| block!(tx.write(byte))) {
+                rprintln!("frame err {:?}", err);
             }
         }
+        telemetry::spawn_after(10.millis()).unwrap();
+    }
+
+    // applies the scheduled values, with a resolution of 1 ms
+    #[task(priority = 1, shared = [params, clock, schedule])]
+    fn actuate(ctx: actuate::Context) {
+        let actuate::SharedResources {
+            params,
+            clock,
+            schedule,
+        } = ctx.shared;
+        apply(schedule, params, clock.now(micros()));
+        actuate::spawn_after(1.millis()).unwrap();
+    }
+
+    fn apply(
+        schedule: &mut Schedule<SCHEDULE>,
+        params: &mut ParameterRegistry<PARAMS>,
+        now: Micros,
+    ) {
+        schedule.poll(now, |id, value| {
+            // checked when queued
+            let outcome = params.execute(&Op::Set(id, value));
+            rprintln!("applied {:?}", outcome);
+        });
+    }
+
+    fn echo(message: &[u8], reply: &mut [u8]) -> Result<usize, (ErrorCode, u32)> {
+        reply[..message.len()].copy_from_slice(message);
+        Ok(message.len())
+    }
+
+    // a table in RAM, a real one would be kept in flash
+    pub struct Calibration {
+        table: [u8; CALIBRATION],
+        size: u32,
+    }
+
+    impl Calibration {
+        fn get(&mut self, blob: BlobId, offset: u32, len: usize) -> Result<&mut [u8], ErrorCode> {
+            if blob != CALIBRATION_BLOB {
+                return Err(ErrorCode::UnknownId);
+            }
+            let start = offset as usize;
+            self.table
+                .get_mut(start..start + len)
+                .ok_or(ErrorCode::OutOfRange)
+        }
+    }
+
+    impl BlobProvider for Calibration {
+        fn size(&mut self, blob: BlobId) -> Result<u32, ErrorCode> {
+            self.get(blob, 0, 0)?;
+            Ok(self.size)
+        }
+
+        fn create(&mut self, blob: BlobId, size: u32) -> Result<(), ErrorCode> {
+            self.get(blob, 0, size as usize)?;
+            self.size = size;
+            Ok(())
+        }
+
+        fn read(&mut self, blob: BlobId, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+            buf.copy_from_slice(self.get(blob, offset, buf.len())?);
+            Ok(())
+        }
+
+        fn write(&mut self, blob: BlobId, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+            self.get(blob, offset, data.len())?.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    // counts the boots in the first backup register (kept while VDDBU is powered), and
+    // draws the id from the TRNG
+    fn session(gpbr: &hal::pac::GPBR, trng: hal::pac::TRNG) -> Session {
+        let boot = gpbr.sys_gpbr[0].read().bits().wrapping_add(1);
+        gpbr.sys_gpbr[0].write(|w| unsafe { w.bits(boot) });
+
+        // the pmc is owned by the clock tokens, only the trng clock is enabled here
+        unsafe { (*hal::pac::PMC::ptr()).pcer1.write(|w| w.pid57().set_bit()) };
+        trng.cr.write(|w| w.key().passwd().enable().set_bit());
+        while trng.isr.read().datrdy().bit_is_clear() {}
+        let id = trng.odata.read().odata().bits();
+        trng.cr.write(|w| w.key().passwd().enable().clear_bit());
+
+        Session { boot, id }
+    }
+
+    // local time, the 32 bit RTT counter wraps after about 6 days
+    fn micros() -> Micros {
+        monotonics::now().duration_since_epoch().ticks() as Micros * 1_000_000 / 8192
+    }
+
+    fn unit_interval(value: &Value) -> Result<(), ErrorCode> {
+        match value {
+            Value::F32(f) if (0.0..=1.0).contains(f) => Ok(()),
+            _ => Err(ErrorCode::OutOfRange),
+        }
+    }
+
+    fn read_count(value: &mut Value) -> Result<(), ErrorCode> {
+        if let Value::U32(v) = value {
+            // stand in for a sensor, counts the reads
+            *v = v.wrapping_add(1);
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,361 @@
+//! Servant side bulk transfer
+//!
+//! Blobs are byte arrays larger than a frame, e.g., calibration tables or captured
+//! sample buffers, identified by a `BlobId` and kept by the application (`BlobProvider`).
+//! The master opens a blob (`Command::BlobOpen`) for reading, or for writing a given
+//! size, reads or writes it at offsets in `BLOB_CHUNK`s (`Command::BlobRead`,
+//! `Command::BlobWrite`), compares the crc of the contents (`Command::BlobChecksum`) and
+//! closes it (`Command::BlobClose`).
+//!
+//! Reads and writes at an offset are idempotent, so retransmissions are harmless. One
+//! blob is open at a time, opening another abandons a write not yet closed.
+//!
+//! Writes go to the `BlobProvider` as they arrive, before the master has compared the
+//! crc. A provider that must keep the previous contents until the new ones are complete
+//! stages the write and swaps it in on `BlobProvider::close`.
+
+use crate::{BlobId, Command, ErrorCode, Response, CKSUM};
+
+/// Blob bytes carried by a `Command::BlobWrite` or `Response::BlobData`
+pub const BLOB_CHUNK: usize = 32;
+
+/// The blobs of the application
+///
+/// Failures are reported to the master, with the blob id or offset as detail.
+pub trait BlobProvider {
+    /// Size of `blob`, e.g., `ErrorCode::UnknownId` if there is no such blob
+    fn size(&mut self, blob: BlobId) -> Result<u32, ErrorCode>;
+
+    /// Prepare `blob` to be written with `size` bytes, e.g., `ErrorCode::OutOfRange` if
+    /// it does not fit or `ErrorCode::ReadOnly`
+    fn create(&mut self, blob: BlobId, size: u32) -> Result<(), ErrorCode>;
+
+    /// Read `buf.len()` bytes at `offset`, within the size of `blob`
+    fn read(&mut self, blob: BlobId, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode>;
+
+    /// Write `data` at `offset`, within the size given to `create`
+    fn write(&mut self, blob: BlobId, offset: u32, data: &[u8]) -> Result<(), ErrorCode>;
+
+    /// The contents written to `blob` were checked by the master and are complete
+    fn close(&mut self, _blob: BlobId) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+impl<P: BlobProvider + ?Sized> BlobProvider for &mut P {
+    fn size(&mut self, blob: BlobId) -> Result<u32, ErrorCode> {
+        (**self).size(blob)
+    }
+
+    fn create(&mut self, blob: BlobId, size: u32) -> Result<(), ErrorCode> {
+        (**self).create(blob, size)
+    }
+
+    fn read(&mut self, blob: BlobId, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        (**self).read(blob, offset, buf)
+    }
+
+    fn write(&mut self, blob: BlobId, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+        (**self).write(blob, offset, data)
+    }
+
+    fn close(&mut self, blob: BlobId) -> Result<(), ErrorCode> {
+        (**self).close(blob)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Open {
+    blob: BlobId,
+    size: u32,
+    write: bool,
+}
+
+/// The bulk transfer state machine
+pub struct Blobs<P> {
+    provider: P,
+    open: Option<Open>,
+}
+
+impl<P: BlobProvider> Blobs<P> {
+    pub const fn new(provider: P) -> Self {
+        Self {
+            provider,
+            open: None,
+        }
+    }
+
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
+    pub fn provider_mut(&mut self) -> &mut P {
+        &mut self.provider
+    }
+
+    /// Handle the blob commands, `None` for other commands
+    pub fn dispatch(&mut self, cmd: &Command) -> Option<Response> {
+        let result = match *cmd {
+            Command::BlobOpen { blob, size, .. } => self.open(blob, size),
+            Command::BlobRead { offset, .. } => self.read(offset),
+            Command::BlobWrite {
+                offset, ref data, ..
+            } => self.write(offset, data),
+            Command::BlobChecksum(_) => self.checksum(),
+            Command::BlobClose(_) => self.close(),
+            _ => return None,
+        };
+        Some(result.unwrap_or_else(|(code, detail)| Response::Error(code, detail)))
+    }
+
+    fn open(&mut self, blob: BlobId, size: Option<u32>) -> Result<Response, (ErrorCode, u32)> {
+        self.open = None;
+        let (size, write) = match size {
+            Some(size) => {
+                self.provider
+                    .create(blob, size)
+                    .map_err(|code| (code, blob))?;
+                (size, true)
+            }
+            None => (
+                self.provider.size(blob).map_err(|code| (code, blob))?,
+                false,
+            ),
+        };
+        self.open = Some(Open { blob, size, write });
+        Ok(Response::BlobOpened { size })
+    }
+
+    fn read(&mut self, offset: u32) -> Result<Response, (ErrorCode, u32)> {
+        let open = self.open.ok_or((ErrorCode::InvalidState, offset))?;
+        if offset > open.size {
+            return Err((ErrorCode::OutOfRange, offset));
+        }
+        let n = (open.size - offset).min(BLOB_CHUNK as u32) as usize;
+        let mut data = heapless::Vec::new();
+        // cannot fail, `n` is at most `BLOB_CHUNK`
+        let _ = data.resize(n, 0);
+        self.provider
+            .read(open.blob, offset, &mut data)
+            .map_err(|code| (code, offset))?;
+        Ok(Response::BlobData(data))
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<Response, (ErrorCode, u32)> {
+        let open = match self.open {
+            Some(open) if open.write => open,
+            _ => return Err((ErrorCode::InvalidState, offset)),
+        };
+        if offset as u64 + data.len() as u64 > open.size as u64 {
+            return Err((ErrorCode::OutOfRange, offset));
+        }
+        self.provider
+            .write(open.blob, offset, data)
+            .map_err(|code| (code, offset))?;
+        Ok(Response::SetOk)
+    }
+
+    fn checksum(&mut self) -> Result<Response, (ErrorCode, u32)> {
+        let open = self.open.ok_or((ErrorCode::InvalidState, 0))?;
+        let mut digest = CKSUM.digest();
+        let mut buf = [0; BLOB_CHUNK];
+        let mut offset = 0;
+        while offset < open.size {
+            let n = (open.size - offset).min(BLOB_CHUNK as u32);
+            let buf = &mut buf[..n as usize];
+            self.provider
+                .read(open.blob, offset, buf)
+                .map_err(|code| (code, offset))?;
+            digest.update(buf);
+            offset += n;
+        }
+        Ok(Response::BlobChecksum(digest.finalize()))
+    }
+
+    fn close(&mut self) -> Result<Response, (ErrorCode, u32)> {
+        // closing again (e.g., the reply was lost) succeeds
+        if let Some(open) = self.open.take() {
+            if open.write {
+                self.provider
+                    .close(open.blob)
+                    .map_err(|code| (code, open.blob))?;
+            }
+        }
+        Ok(Response::SetOk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV: u32 = 0b001;
+
+    /// Blob 0 of 80 bytes, blob 1 is read-only, counting the closed writes
+    struct Ram {
+        data: [u8; 80],
+        size: u32,
+        closed: u32,
+    }
+
+    impl BlobProvider for Ram {
+        fn size(&mut self, blob: BlobId) -> Result<u32, ErrorCode> {
+            match blob {
+                0 | 1 => Ok(self.size),
+                _ => Err(ErrorCode::UnknownId),
+            }
+        }
+
+        fn create(&mut self, blob: BlobId, size: u32) -> Result<(), ErrorCode> {
+            match blob {
+                0 if size as usize <= self.data.len() => {
+                    self.size = size;
+                    Ok(())
+                }
+                0 => Err(ErrorCode::OutOfRange),
+                1 => Err(ErrorCode::ReadOnly),
+                _ => Err(ErrorCode::UnknownId),
+            }
+        }
+
+        fn read(&mut self, _blob: BlobId, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+            let start = offset as usize;
+            buf.copy_from_slice(&self.data[start..start + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, _blob: BlobId, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+            let start = offset as usize;
+            self.data[start..start + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn close(&mut self, _blob: BlobId) -> Result<(), ErrorCode> {
+            self.closed += 1;
+            Ok(())
+        }
+    }
+
+    fn blobs() -> Blobs<Ram> {
+        Blobs::new(Ram {
+            data: core::array::from_fn(|i| i as u8),
+            size: 80,
+            closed: 0,
+        })
+    }
+
+    fn open(blob: BlobId, size: Option<u32>) -> Command {
+        Command::BlobOpen {
+            blob,
+            size,
+            dev: DEV,
+        }
+    }
+
+    fn write(offset: u32, data: &[u8]) -> Command {
+        Command::BlobWrite {
+            offset,
+            data: heapless::Vec::from_slice(data).unwrap(),
+            dev: DEV,
+        }
+    }
+
+    fn read(offset: u32) -> Command {
+        Command::BlobRead { offset, dev: DEV }
+    }
+
+    #[test]
+    fn read_in_chunks() {
+        let mut blobs = blobs();
+        let opened = Some(Response::BlobOpened { size: 80 });
+        assert_eq!(blobs.dispatch(&open(0, None)), opened);
+        let mut contents = heapless::Vec::<u8, 80>::new();
+        for offset in [0, 32, 64] {
+            let Some(Response::BlobData(data)) = blobs.dispatch(&read(offset)) else {
+                panic!("no data at {}", offset);
+            };
+            contents.extend_from_slice(&data).unwrap();
+        }
+        assert_eq!(contents, blobs.provider().data);
+        // at the end
+        let empty = Some(Response::BlobData(heapless::Vec::new()));
+        assert_eq!(blobs.dispatch(&read(80)), empty);
+        assert_eq!(
+            blobs.dispatch(&read(81)),
+            Some(Response::Error(ErrorCode::OutOfRange, 81))
+        );
+        let crc = CKSUM.checksum(&blobs.provider().data);
+        let checksum = Command::BlobChecksum(DEV);
+        assert_eq!(blobs.dispatch(&checksum), Some(Response::BlobChecksum(crc)));
+        // read-only access, not written
+        let close = Command::BlobClose(DEV);
+        assert_eq!(blobs.dispatch(&close), Some(Response::SetOk));
+        assert_eq!(blobs.provider().closed, 0);
+        assert_eq!(blobs.dispatch(&Command::Ping(DEV)), None);
+    }
+
+    #[test]
+    fn write_then_close() {
+        let mut blobs = blobs();
+        let opened = Some(Response::BlobOpened { size: 40 });
+        assert_eq!(blobs.dispatch(&open(0, Some(40))), opened);
+        let new = [0xa5; 40];
+        // retransmitted chunks are written again
+        for (offset, data) in [(0, &new[..32]), (0, &new[..32]), (32, &new[32..])] {
+            let resp = blobs.dispatch(&write(offset, data));
+            assert_eq!(resp, Some(Response::SetOk));
+        }
+        assert_eq!(
+            blobs.dispatch(&write(32, &new[..9])),
+            Some(Response::Error(ErrorCode::OutOfRange, 32))
+        );
+        let checksum = Command::BlobChecksum(DEV);
+        let crc = CKSUM.checksum(&new);
+        assert_eq!(blobs.dispatch(&checksum), Some(Response::BlobChecksum(crc)));
+        let close = Command::BlobClose(DEV);
+        assert_eq!(blobs.dispatch(&close), Some(Response::SetOk));
+        assert_eq!(blobs.provider().closed, 1);
+        // closing again (lost reply) succeeds, closed once
+        assert_eq!(blobs.dispatch(&close), Some(Response::SetOk));
+        assert_eq!(blobs.provider().closed, 1);
+        assert_eq!(
+            blobs.dispatch(&checksum),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+    }
+
+    #[test]
+    fn writes_are_in_place() {
+        let mut blobs = blobs();
+        blobs.dispatch(&open(0, Some(40)));
+        blobs.dispatch(&write(0, &[0xa5; 32]));
+        // abandoned by opening another, not closed but already overwritten
+        let opened = Some(Response::BlobOpened { size: 40 });
+        assert_eq!(blobs.dispatch(&open(0, None)), opened);
+        assert_eq!(blobs.provider().closed, 0);
+        assert_eq!(blobs.provider().data[..32], [0xa5; 32]);
+        assert_eq!(
+            blobs.dispatch(&write(0, &[1])),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+    }
+
+    #[test]
+    fn refused_opens() {
+        let mut blobs = blobs();
+        let error = |code, detail| Some(Response::Error(code, detail));
+        assert_eq!(
+            blobs.dispatch(&open(2, None)),
+            error(ErrorCode::UnknownId, 2)
+        );
+        assert_eq!(
+            blobs.dispatch(&open(1, Some(8))),
+            error(ErrorCode::ReadOnly, 1)
+        );
+        assert_eq!(
+            blobs.dispatch(&open(0, Some(81))),
+            error(ErrorCode::OutOfRange, 0)
+        );
+        assert_eq!(blobs.dispatch(&read(0)), error(ErrorCode::InvalidState, 0));
+    }
+}
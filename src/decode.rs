@@ -0,0 +1,319 @@
+//! Frame payload decoding
+//!
+//! `from_bytes` follows the `ssmarshal` format, but reports input running out as an
+//! error. (`ssmarshal::deserialize` trips a debug assertion instead, which a truncated
+//! payload or one of another type passing the crc would otherwise turn into a panic.)
+
+use crate::FrameError;
+use serde::de::{self, Deserialize, DeserializeSeed, IntoDeserializer, Visitor};
+
+/// Deserialize T from the start of `buf`, returns T and the number of bytes used
+pub(crate) fn from_bytes<T>(buf: &[u8]) -> Result<(T, usize), FrameError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut de = SliceDeserializer { buf, idx: 0 };
+    let t = T::deserialize(&mut de).map_err(|_| FrameError::Deserialize)?;
+    Ok((t, de.idx))
+}
+
+#[derive(Debug)]
+enum DecodeError {
+    EndOfStream,
+    InvalidRepresentation,
+    NotSupported,
+    Custom,
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::EndOfStream => f.write_str("end of stream"),
+            Self::InvalidRepresentation => f.write_str("invalid representation"),
+            Self::NotSupported => f.write_str("feature not supported"),
+            Self::Custom => f.write_str("custom error"),
+        }
+    }
+}
+
+impl core::error::Error for DecodeError {}
+
+impl de::Error for DecodeError {
+    fn custom<T: core::fmt::Display>(_msg: T) -> Self {
+        Self::Custom
+    }
+}
+
+/// Deserializer following the `ssmarshal` format, bounds checked
+struct SliceDeserializer<'de> {
+    buf: &'de [u8],
+    idx: usize,
+}
+
+impl<'de> SliceDeserializer<'de> {
+    fn read<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let bytes = self
+            .buf
+            .get(self.idx..self.idx + N)
+            .ok_or(DecodeError::EndOfStream)?;
+        self.idx += N;
+        // cannot fail, `bytes` has length N
+        Ok(bytes.try_into().unwrap_or([0; N]))
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read::<1>()?[0])
+    }
+
+    fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.read()?))
+    }
+
+    fn read_char(&mut self) -> Result<char, DecodeError> {
+        let rest = &self.buf[self.idx..];
+        let len = match rest.first() {
+            None => return Err(DecodeError::EndOfStream),
+            Some(b) if b & 0x80 == 0 => 1,
+            Some(b) if b & 0xe0 == 0xc0 => 2,
+            Some(b) if b & 0xf0 == 0xe0 => 3,
+            Some(b) if b & 0xf8 == 0xf0 => 4,
+            Some(_) => return Err(DecodeError::InvalidRepresentation),
+        };
+        let bytes = rest.get(..len).ok_or(DecodeError::EndOfStream)?;
+        let c = core::str::from_utf8(bytes)
+            .ok()
+            .and_then(|s| s.chars().next())
+            .ok_or(DecodeError::InvalidRepresentation)?;
+        self.idx += len;
+        Ok(c)
+    }
+}
+
+struct SeqAccess<'a, 'de> {
+    de: &'a mut SliceDeserializer<'de>,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'_, 'de> {
+    type Error = DecodeError;
+
+    fn next_element_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<Option<S::Value>, DecodeError> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut SliceDeserializer<'de> {
+    type Error = DecodeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        match self.read_u8()? {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            _ => Err(DecodeError::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_i8(i8::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_i16(i16::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_i32(i32::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_i64(i64::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_u8(self.read_u8()?)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_u16(u16::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_u32(u32::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_u64(self.read_u64()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_f32(f32::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_f64(f64::from_le_bytes(self.read()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_char(self.read_char()?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        match self.read_u8()? {
+            0 => visitor.visit_none(),
+            1 => visitor.visit_some(self),
+            _ => Err(DecodeError::InvalidRepresentation),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DecodeError> {
+        // ssmarshal encodes the length as an u64
+        let len = usize::try_from(self.read_u64()?).map_err(|_| DecodeError::EndOfStream)?;
+        visitor.visit_seq(SeqAccess { de: self, len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        visitor.visit_seq(SeqAccess { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        _visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        Err(DecodeError::NotSupported)
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut SliceDeserializer<'de> {
+    type Error = DecodeError;
+    type Variant = Self;
+
+    fn variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<(S::Value, Self), DecodeError> {
+        let index = self.read_u8()? as u32;
+        let value = seed.deserialize(index.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut SliceDeserializer<'de> {
+    type Error = DecodeError;
+
+    fn unit_variant(self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: DeserializeSeed<'de>>(
+        self,
+        seed: S,
+    ) -> Result<S::Value, DecodeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DecodeError> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
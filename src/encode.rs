@@ -0,0 +1,563 @@
+//! Frame encoding without intermediate copies
+//!
+//! `encode_in_place` cobs encodes a payload already residing in the output buffer,
+//! while `encode_to_sink` streams serialization, crc and cobs encoding byte by byte
+//! to a sink, so no output buffer is needed at all.
+
+use crate::{FrameError, CKSUM};
+use corncobs::ZERO;
+use serde::ser::{self, Serialize};
+
+/// Longest run of non-zero bytes that can be cobs encoded
+pub(crate) const MAX_RUN: usize = 254;
+
+/// Head room needed in front of a payload for in place encoding into a buffer of `n` bytes
+pub(crate) const fn head_room(n: usize) -> usize {
+    n / MAX_RUN + 1
+}
+
+/// Cobs encode `len` bytes residing at `buf[start..]` into the beginning of `buf`
+///
+/// Each run is moved at most one byte towards the front of the buffer, so `start`
+/// must be at least `head_room(len)`. The encoding is identical to `corncobs::encode_buf`.
+/// Returns the number of encoded bytes, including the terminating `ZERO`.
+pub(crate) fn encode_in_place(buf: &mut [u8], start: usize, len: usize) -> usize {
+    let end = start + len;
+    let mut read = start;
+    let mut write = 0;
+    loop {
+        let max = usize::min(end - read, MAX_RUN);
+        let run = buf[read..read + max]
+            .iter()
+            .position(|b| *b == ZERO)
+            .unwrap_or(max);
+        buf.copy_within(read..read + run, write + 1);
+        buf[write] = run as u8 + 1;
+        write += run + 1;
+        read += run;
+        if read == end {
+            break;
+        }
+        if run < MAX_RUN {
+            // the zero is implied by the run length
+            read += 1;
+        }
+    }
+    buf[write] = ZERO;
+    write + 1
+}
+
+/// Errors raised by `encode_to_sink`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkError<E> {
+    /// The value could not be framed
+    Frame(FrameError),
+    /// The sink failed
+    Sink(E),
+}
+
+/// Serialize T with crc and cobs encoding, emitting each byte to `sink`
+///
+/// The crc is computed incrementally as bytes go out, only a single cobs run (at most 254 bytes)
+/// is buffered. Returns the number of bytes emitted, including the terminating `ZERO`.
+pub fn encode_to_sink<T, E, F>(t: &T, sink: F) -> Result<usize, SinkError<E>>
+where
+    T: Serialize,
+    F: FnMut(u8) -> Result<(), E>,
+{
+    let mut ser = StreamSerializer {
+        cobs: CobsWriter::new(sink),
+        digest: CKSUM.digest(),
+        sink_error: None,
+    };
+    if let Err(err) = t.serialize(&mut ser) {
+        return Err(match (err, ser.sink_error) {
+            (StreamError::Sink, Some(e)) => SinkError::Sink(e),
+            _ => SinkError::Frame(FrameError::Serialize),
+        });
+    }
+    let StreamSerializer {
+        mut cobs, digest, ..
+    } = ser;
+    for byte in digest.finalize().to_le_bytes() {
+        cobs.push(byte).map_err(SinkError::Sink)?;
+    }
+    cobs.finish().map_err(SinkError::Sink)
+}
+
+/// Streaming cobs encoder, buffering a single run
+struct CobsWriter<F> {
+    sink: F,
+    run: [u8; MAX_RUN],
+    len: usize,
+    emitted: usize,
+}
+
+impl<E, F: FnMut(u8) -> Result<(), E>> CobsWriter<F> {
+    fn new(sink: F) -> Self {
+        Self {
+            sink,
+            run: [0u8; MAX_RUN],
+            len: 0,
+            emitted: 0,
+        }
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), E> {
+        (self.sink)(byte)?;
+        self.emitted += 1;
+        Ok(())
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), E> {
+        if self.len == MAX_RUN {
+            // a full run does not imply a zero
+            self.flush()?;
+        }
+        if byte == ZERO {
+            self.flush()
+        } else {
+            self.run[self.len] = byte;
+            self.len += 1;
+            Ok(())
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), E> {
+        self.emit(self.len as u8 + 1)?;
+        for i in 0..self.len {
+            self.emit(self.run[i])?;
+        }
+        self.len = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<usize, E> {
+        self.flush()?;
+        self.emit(ZERO)?;
+        Ok(self.emitted)
+    }
+}
+
+#[derive(Debug)]
+enum StreamError {
+    Sink,
+    NotSupported,
+    TooManyVariants,
+    Custom,
+}
+
+impl core::fmt::Display for StreamError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Sink => f.write_str("sink error"),
+            Self::NotSupported => f.write_str("feature not supported"),
+            Self::TooManyVariants => f.write_str("too many variants"),
+            Self::Custom => f.write_str("custom error"),
+        }
+    }
+}
+
+impl core::error::Error for StreamError {}
+
+impl ser::Error for StreamError {
+    fn custom<T: core::fmt::Display>(_msg: T) -> Self {
+        Self::Custom
+    }
+}
+
+/// Serializer following the `ssmarshal` format, feeding crc and cobs encoder
+struct StreamSerializer<'c, E, F> {
+    cobs: CobsWriter<F>,
+    digest: crc::Digest<'c, u32>,
+    sink_error: Option<E>,
+}
+
+impl<'c, E, F: FnMut(u8) -> Result<(), E>> StreamSerializer<'c, E, F> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), StreamError> {
+        self.digest.update(bytes);
+        for byte in bytes {
+            if let Err(e) = self.cobs.push(*byte) {
+                self.sink_error = Some(e);
+                return Err(StreamError::Sink);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_variant(&mut self, variant_index: u32) -> Result<(), StreamError> {
+        let index = u8::try_from(variant_index).map_err(|_| StreamError::TooManyVariants)?;
+        self.write(&[index])
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::Serializer
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = ser::Impossible<(), StreamError>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<(), StreamError> {
+        self.write(&[v as u8])
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), StreamError> {
+        self.write(&[v])
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), StreamError> {
+        self.write(&v.to_le_bytes())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), StreamError> {
+        self.write(&v.to_bits().to_le_bytes())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), StreamError> {
+        self.write(&v.to_bits().to_le_bytes())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), StreamError> {
+        let mut utf8 = [0u8; 4];
+        self.write(v.encode_utf8(&mut utf8).as_bytes())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(), StreamError> {
+        Err(StreamError::NotSupported)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), StreamError> {
+        Err(StreamError::NotSupported)
+    }
+
+    fn serialize_none(self) -> Result<(), StreamError> {
+        self.write(&[0])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), StreamError> {
+        self.write(&[1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), StreamError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), StreamError> {
+        self.write_variant(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), StreamError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<(), StreamError> {
+        self.write_variant(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self, StreamError> {
+        // ssmarshal encodes the length as an u64
+        let len = len.ok_or(StreamError::NotSupported)?;
+        self.write(&(len as u64).to_le_bytes())?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, StreamError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, StreamError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, StreamError> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, StreamError> {
+        Err(StreamError::NotSupported)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, StreamError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self, StreamError> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+
+    fn collect_str<T: core::fmt::Display + ?Sized>(self, _value: &T) -> Result<(), StreamError> {
+        Err(StreamError::NotSupported)
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeSeq
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeTuple
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeTupleStruct
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeTupleVariant
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeStruct
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+impl<'a, 'c, E, F: FnMut(u8) -> Result<(), E>> ser::SerializeStructVariant
+    for &'a mut StreamSerializer<'c, E, F>
+{
+    type Ok = ();
+    type Error = StreamError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), StreamError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), StreamError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serialize_crc_cobs, Frame, WireSize};
+    use serde_derive::Serialize;
+
+    const LEN: usize = 600;
+
+    #[derive(Serialize, WireSize)]
+    struct Payload(heapless::Vec<u8, LEN>);
+
+    /// Byte patterns around the run length limit
+    fn patterns() -> impl Iterator<Item = heapless::Vec<u8, LEN>> {
+        let runs = [0, 1, 253, 254, 255, 256, 508, 509, LEN];
+        let fills = runs.into_iter().flat_map(|n| {
+            [
+                (0..n).map(|_| 1).collect(),
+                (0..n).map(|_| 0).collect(),
+                // a zero right after the run
+                (0..LEN).map(|i| (i != n) as u8).collect(),
+            ]
+        });
+        let mut seed = 1u32;
+        let random = (0..32).map(move |_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let len = (seed >> 8) as usize % LEN;
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    // mostly non-zero, so long runs occur
+                    ((seed >> 16) % 64) as u8
+                })
+                .collect()
+        });
+        fills.chain(random)
+    }
+
+    #[test]
+    fn in_place_matches_corncobs() {
+        for payload in patterns() {
+            let len = payload.len();
+            let mut expected = [0u8; 2 * LEN];
+            let n = corncobs::encode_buf(&payload, &mut expected);
+
+            let start = head_room(len);
+            let mut buf = [0xaau8; 2 * LEN];
+            buf[start..start + len].copy_from_slice(&payload);
+            assert_eq!(encode_in_place(&mut buf, start, len), n, "length {}", len);
+            assert_eq!(buf[..n], expected[..n], "length {}", len);
+        }
+    }
+
+    #[test]
+    fn sink_matches_corncobs() {
+        for data in patterns() {
+            let payload = Payload(data);
+            let mut raw = [0u8; 2 * LEN];
+            let used = ssmarshal::serialize(&mut raw, &payload).unwrap();
+            let crc = CKSUM.checksum(&raw[..used]).to_le_bytes();
+            raw[used..used + 4].copy_from_slice(&crc);
+            let mut expected = [0u8; 2 * LEN];
+            let n = corncobs::encode_buf(&raw[..used + 4], &mut expected);
+
+            let mut out = [0u8; 2 * LEN];
+            let mut i = 0;
+            let emitted = encode_to_sink(&payload, |byte| {
+                out[i] = byte;
+                i += 1;
+                Ok::<_, ()>(())
+            });
+            assert_eq!(emitted, Ok(n));
+            assert_eq!(out[..n], expected[..n]);
+
+            let mut buf = Payload::frame_buf();
+            assert_eq!(serialize_crc_cobs(&payload, &mut buf), Ok(&expected[..n]));
+        }
+    }
+
+    #[test]
+    fn fills_frame_buf() {
+        // no zeros anywhere, not even in the length, gives the largest frame
+        #[derive(Serialize, WireSize)]
+        struct Full([[u8; 30]; 20]);
+
+        let payload = Full([[0xa5; 30]; 20]);
+        let mut buf = Full::frame_buf();
+        let n = serialize_crc_cobs(&payload, &mut buf).unwrap().len();
+        assert_eq!(n, Full::MAX_FRAME_SIZE);
+        let mut raw = [0u8; 2 * LEN];
+        let used = ssmarshal::serialize(&mut raw, &payload).unwrap();
+        let cksum = CKSUM.checksum(&raw[..used]).to_le_bytes();
+        raw[used..used + 4].copy_from_slice(&cksum);
+        let mut expected = [0u8; 2 * LEN];
+        assert_eq!(corncobs::encode_buf(&raw[..used + 4], &mut expected), n);
+        assert_eq!(buf[..n], expected[..n]);
+
+        let mut out = Full::frame_buf();
+        let mut i = 0;
+        let emitted = encode_to_sink(&payload, |byte| {
+            *out.get_mut(i).ok_or(())? = byte;
+            i += 1;
+            Ok::<_, ()>(())
+        });
+        assert_eq!(emitted, Ok(n));
+        assert_eq!(out[..n], expected[..n]);
+    }
+}
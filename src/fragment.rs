@@ -0,0 +1,371 @@
+//! Messages larger than a frame
+//!
+//! An application message (e.g., a serialized structure) is split into numbered
+//! fragments of at most the negotiated MTU, each carried by a frame of its own, and
+//! reassembled on the other side into a caller provided buffer (`Reassembler`). A
+//! message not completed within the timeout is discarded.
+//!
+//! The master sends a message to a servant by `Command::Fragment`s, the servant
+//! (`Messages`) hands the complete message to the application, whose reply message is
+//! read back by `Command::FragmentRead`. `Command::Mtu` negotiates the fragment size and
+//! tells the largest message the servant accepts.
+
+use crate::{Command, ErrorCode, Micros, MsgId, Mtu, Response};
+
+/// Largest fragment carried by a frame
+pub const FRAGMENT: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+struct Partial {
+    msg: MsgId,
+    count: u16,
+    /// Index of the next fragment
+    next: u16,
+    len: usize,
+    /// When the last fragment was received
+    last: Micros,
+}
+
+/// Reassembles the fragments of a message into a buffer
+pub struct Reassembler<B> {
+    buf: B,
+    partial: Option<Partial>,
+    timeout: Micros,
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Reassembler<B> {
+    /// Reassemble into `buf`, discarding messages with no fragment for `timeout`
+    pub const fn new(buf: B, timeout: Micros) -> Self {
+        Self {
+            buf,
+            partial: None,
+            timeout,
+        }
+    }
+
+    /// Largest message
+    pub fn capacity(&self) -> usize {
+        self.buf.as_ref().len()
+    }
+
+    /// Add fragment `index` of `count` of message `msg`, received at `now`, returns the
+    /// message once complete
+    ///
+    /// Fragment 0 starts a new message, the others must follow in order, repeated
+    /// fragments are ignored.
+    pub fn push(
+        &mut self,
+        msg: MsgId,
+        index: u16,
+        count: u16,
+        data: &[u8],
+        now: Micros,
+    ) -> Result<Option<&[u8]>, (ErrorCode, u32)> {
+        self.expire(now);
+        if index >= count {
+            return Err((ErrorCode::OutOfRange, index as u32));
+        }
+        if index == 0 {
+            self.partial = Some(Partial {
+                msg,
+                count,
+                next: 0,
+                len: 0,
+                last: now,
+            });
+        }
+        let partial = match self.partial {
+            Some(ref mut partial) if (partial.msg, partial.count) == (msg, count) => partial,
+            _ => return Err((ErrorCode::InvalidState, index as u32)),
+        };
+        if index < partial.next {
+            return Ok(None);
+        }
+        if index > partial.next {
+            return Err((ErrorCode::OutOfOrder, partial.next as u32));
+        }
+        let buf = self.buf.as_mut();
+        let Some(dest) = buf.get_mut(partial.len..partial.len + data.len()) else {
+            self.partial = None;
+            return Err((ErrorCode::OutOfRange, index as u32));
+        };
+        dest.copy_from_slice(data);
+        partial.len += data.len();
+        partial.next += 1;
+        partial.last = now;
+        if partial.next < count {
+            return Ok(None);
+        }
+        let len = partial.len;
+        self.partial = None;
+        Ok(Some(&self.buf.as_ref()[..len]))
+    }
+
+    /// Discard an incomplete message timed out at `now`, returns whether one was
+    pub fn expire(&mut self, now: Micros) -> bool {
+        match self.partial {
+            Some(partial) if now.saturating_sub(partial.last) > self.timeout => {
+                self.partial = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Servant side message exchange
+///
+/// Incoming messages are reassembled into the `rx` buffer, the reply message of the
+/// application is written into the `tx` buffer, where it stays for the master to read
+/// until the next message.
+pub struct Messages<B> {
+    rx: Reassembler<B>,
+    tx: B,
+    /// The message replied to, and the length of the reply
+    reply: Option<(MsgId, usize)>,
+    /// Largest fragment accepted, and the one negotiated
+    limit: u16,
+    mtu: u16,
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Messages<B> {
+    pub const fn new(rx: B, tx: B, timeout: Micros) -> Self {
+        Self {
+            rx: Reassembler::new(rx, timeout),
+            tx,
+            reply: None,
+            limit: FRAGMENT as u16,
+            mtu: FRAGMENT as u16,
+        }
+    }
+
+    /// Accept fragments of at most `mtu` bytes, clamped to 1 to `FRAGMENT` (32) bytes
+    pub const fn with_mtu(mut self, mtu: u16) -> Self {
+        let mtu = if mtu == 0 { 1 } else { mtu };
+        if mtu < self.limit {
+            self.limit = mtu;
+            self.mtu = mtu;
+        }
+        self
+    }
+
+    /// Discard an incomplete message timed out at `now`
+    pub fn poll(&mut self, now: Micros) -> bool {
+        self.rx.expire(now)
+    }
+
+    /// Handle the message commands received at `now`, `None` for other commands
+    ///
+    /// `handle` is called with each complete message and the reply buffer, and returns
+    /// the length of the reply message (0 for none).
+    pub fn dispatch(
+        &mut self,
+        cmd: &Command,
+        now: Micros,
+        handle: impl FnOnce(&[u8], &mut [u8]) -> Result<usize, (ErrorCode, u32)>,
+    ) -> Option<Response> {
+        let result = match *cmd {
+            Command::Mtu { mtu: 0, .. } => Err((ErrorCode::OutOfRange, 0)),
+            Command::Mtu { mtu, .. } => {
+                self.mtu = mtu.min(self.limit);
+                Ok(Response::Mtu(Mtu {
+                    fragment: self.mtu,
+                    message: self.rx.capacity() as u32,
+                }))
+            }
+            Command::Fragment {
+                msg,
+                index,
+                count,
+                ref data,
+                ..
+            } => self.fragment(msg, index, count, data, now, handle),
+            Command::FragmentRead { msg, index, .. } => self.read(msg, index),
+            _ => return None,
+        };
+        Some(result.unwrap_or_else(|(code, detail)| Response::Error(code, detail)))
+    }
+
+    fn fragment(
+        &mut self,
+        msg: MsgId,
+        index: u16,
+        count: u16,
+        data: &[u8],
+        now: Micros,
+        handle: impl FnOnce(&[u8], &mut [u8]) -> Result<usize, (ErrorCode, u32)>,
+    ) -> Result<Response, (ErrorCode, u32)> {
+        if data.len() > self.mtu as usize {
+            return Err((ErrorCode::OutOfRange, index as u32));
+        }
+        if index == 0 {
+            self.reply = None;
+        }
+        let Some(message) = self.rx.push(msg, index, count, data, now)? else {
+            return Ok(Response::SetOk);
+        };
+        let tx = self.tx.as_mut();
+        let size = handle(message, tx)?.min(tx.len());
+        self.reply = Some((msg, size));
+        Ok(Response::Message {
+            msg,
+            size: size as u32,
+        })
+    }
+
+    fn read(&mut self, msg: MsgId, index: u16) -> Result<Response, (ErrorCode, u32)> {
+        let size = match self.reply {
+            Some((m, size)) if m == msg => size,
+            _ => return Err((ErrorCode::InvalidState, index as u32)),
+        };
+        let mtu = self.mtu as usize;
+        let count = size.div_ceil(mtu).max(1) as u16;
+        if index >= count {
+            return Err((ErrorCode::OutOfRange, index as u32));
+        }
+        let start = index as usize * mtu;
+        let data = &self.tx.as_ref()[start..size.min(start + mtu)];
+        Ok(Response::Fragment {
+            msg,
+            index,
+            count,
+            data: data.iter().copied().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV: u32 = 0b001;
+    const TIMEOUT: Micros = 1_000;
+
+    fn reassembler() -> Reassembler<[u8; 64]> {
+        Reassembler::new([0; 64], TIMEOUT)
+    }
+
+    #[test]
+    fn fragments_in_order() {
+        let mut rx = reassembler();
+        assert_eq!(rx.push(1, 0, 3, b"ab", 0), Ok(None));
+        assert_eq!(rx.push(1, 1, 3, b"cd", 10), Ok(None));
+        assert_eq!(rx.push(1, 2, 3, b"e", 20), Ok(Some(&b"abcde"[..])));
+    }
+
+    #[test]
+    fn out_of_order_and_duplicate_fragments() {
+        let mut rx = reassembler();
+        assert_eq!(rx.push(1, 0, 3, b"ab", 0), Ok(None));
+        // fragment 1 lost, the sender is told which one is expected
+        assert_eq!(rx.push(1, 2, 3, b"e", 10), Err((ErrorCode::OutOfOrder, 1)));
+        assert_eq!(rx.push(1, 1, 3, b"cd", 20), Ok(None));
+        // retransmitted
+        assert_eq!(rx.push(1, 1, 3, b"cd", 30), Ok(None));
+        assert_eq!(rx.push(1, 0, 3, b"ab", 40), Ok(None));
+        assert_eq!(rx.push(1, 1, 3, b"cd", 50), Ok(None));
+        assert_eq!(rx.push(1, 2, 3, b"e", 60), Ok(Some(&b"abcde"[..])));
+        // of another message
+        assert_eq!(
+            rx.push(2, 1, 3, b"cd", 70),
+            Err((ErrorCode::InvalidState, 1))
+        );
+    }
+
+    #[test]
+    fn missing_fragment_times_out() {
+        let mut rx = reassembler();
+        assert_eq!(rx.push(1, 0, 2, b"ab", 0), Ok(None));
+        assert!(!rx.expire(TIMEOUT));
+        assert!(rx.expire(TIMEOUT + 1));
+        assert_eq!(
+            rx.push(1, 1, 2, b"cd", TIMEOUT + 2),
+            Err((ErrorCode::InvalidState, 1))
+        );
+    }
+
+    #[test]
+    fn message_larger_than_buffer() {
+        let mut rx = Reassembler::new([0; 4], TIMEOUT);
+        assert_eq!(rx.push(1, 0, 2, b"abc", 0), Ok(None));
+        assert_eq!(rx.push(1, 1, 2, b"de", 0), Err((ErrorCode::OutOfRange, 1)));
+        // discarded
+        assert_eq!(rx.push(1, 1, 2, b"d", 0), Err((ErrorCode::InvalidState, 1)));
+    }
+
+    fn messages(mtu: u16) -> Messages<[u8; 64]> {
+        Messages::new([0; 64], [0; 64], TIMEOUT).with_mtu(mtu)
+    }
+
+    fn fragment(index: u16, count: u16, data: &[u8]) -> Command {
+        Command::Fragment {
+            msg: 1,
+            index,
+            count,
+            data: data.iter().copied().collect(),
+            dev: DEV,
+        }
+    }
+
+    fn read(index: u16) -> Command {
+        Command::FragmentRead {
+            msg: 1,
+            index,
+            dev: DEV,
+        }
+    }
+
+    fn echo(message: &[u8], reply: &mut [u8]) -> Result<usize, (ErrorCode, u32)> {
+        reply[..message.len()].copy_from_slice(message);
+        Ok(message.len())
+    }
+
+    #[test]
+    fn message_of_exactly_one_mtu() {
+        let mut messages = messages(8);
+        let data = *b"01234567";
+        assert_eq!(
+            messages.dispatch(&fragment(0, 1, &data), 0, echo),
+            Some(Response::Message { msg: 1, size: 8 })
+        );
+        let reply = Response::Fragment {
+            msg: 1,
+            index: 0,
+            count: 1,
+            data: data.iter().copied().collect(),
+        };
+        assert_eq!(messages.dispatch(&read(0), 0, echo), Some(reply));
+        assert_eq!(
+            messages.dispatch(&read(1), 0, echo),
+            Some(Response::Error(ErrorCode::OutOfRange, 1))
+        );
+        // a byte more takes two fragments
+        assert_eq!(
+            messages.dispatch(&fragment(0, 1, b"012345678"), 0, echo),
+            Some(Response::Error(ErrorCode::OutOfRange, 0))
+        );
+    }
+
+    #[test]
+    fn mtu_is_clamped() {
+        let mtu = |messages: &mut Messages<[u8; 64]>, mtu| {
+            let cmd = Command::Mtu { mtu, dev: DEV };
+            match messages.dispatch(&cmd, 0, echo) {
+                Some(Response::Mtu(mtu)) => mtu.fragment,
+                resp => panic!("unexpected {:?}", resp),
+            }
+        };
+        assert_eq!(mtu(&mut messages(64), 64), FRAGMENT as u16);
+        assert_eq!(mtu(&mut messages(8), 16), 8);
+        // at least one byte
+        let mut messages = messages(0);
+        assert_eq!(mtu(&mut messages, 16), 1);
+        messages.dispatch(&fragment(0, 2, b"a"), 0, echo);
+        let resp = messages.dispatch(&fragment(1, 2, b"b"), 0, echo);
+        assert_eq!(resp, Some(Response::Message { msg: 1, size: 2 }));
+        assert!(matches!(
+            messages.dispatch(&read(1), 0, echo),
+            Some(Response::Fragment { count: 2, .. })
+        ));
+    }
+}
@@ -1,64 +1,939 @@
 #![no_std]
 
+// allows derived code to refer to `::master_and_servant` from within this crate
+extern crate self as master_and_servant;
+
 use serde_derive::{Deserialize, Serialize};
 
+mod blob;
+mod decode;
+mod encode;
+mod fragment;
+mod registry;
+mod schedule;
+mod servant;
+mod signature;
+mod telemetry;
+mod time;
+mod update;
+mod value;
+mod wire;
+pub use blob::{BlobProvider, Blobs, BLOB_CHUNK};
+pub use encode::{encode_to_sink, SinkError};
+pub use fragment::{Messages, Reassembler, FRAGMENT};
+pub use heapless;
+pub use master_and_servant_derive::WireSize;
+pub use registry::{Access, ParameterRegistry, ReadHook, Slot, WriteHook};
+pub use schedule::Schedule;
+pub use servant::{multicast_slot, Outgoing, Servant, SLOT_BYTES};
+pub use signature::{
+    signed_message, trailer, PublicKey, Verifier, SIGNATURE_MAGIC, SIGNED_MESSAGE, TRAILER,
+};
+pub use telemetry::Subscriptions;
+pub use time::Clock;
+pub use update::{digest, Digest, RamStaging, Staging, UpdateState, Updater, CHUNK};
+pub use value::{Fixed, Value};
+pub use wire::{const_max, schema_hash, schema_mix, Frame, WireSize, SCHEMA_SEED};
+
 // we could use new-type pattern here but let's keep it simple
 pub type Id = u32;
 pub type DevId = u32;
 pub type Parameter = u32;
+pub type Seq = u16;
+pub type BlobId = u32;
+pub type MsgId = u16;
+/// Time in microseconds
+pub type Micros = u64;
+
+/// Sequence number used by the servant when the request could not be decoded
+pub const SEQ_UNKNOWN: Seq = 0;
+
+/// Reserved address, executed by all servants without reply
+pub const BROADCAST: DevId = DevId::MAX;
+
+/// Largest number of sub-commands in a `Command::Batch`
+pub const BATCH: usize = 4;
+
+/// `DevId` of a servant waiting for assignment, see `Command::Claim`
+pub const UNASSIGNED: DevId = 0;
+
+/// 128-bit unique hardware id of a servant, most significant word first
+///
+/// `u128` is not supported by `ssmarshal`, hence the words.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, WireSize,
+)]
+pub struct Uid(pub [u32; 4]);
+
+impl Uid {
+    pub const BITS: u8 = 128;
+
+    pub const fn from_u128(v: u128) -> Self {
+        Self([
+            (v >> 96) as u32,
+            (v >> 64) as u32,
+            (v >> 32) as u32,
+            v as u32,
+        ])
+    }
+
+    pub const fn to_u128(self) -> u128 {
+        let [a, b, c, d] = self.0;
+        (a as u128) << 96 | (b as u128) << 64 | (c as u128) << 32 | d as u128
+    }
+
+    /// Whether the first `len` bits (most significant first) equal those of `prefix`
+    pub const fn has_prefix(self, prefix: Uid, len: u8) -> bool {
+        let diff = self.to_u128() ^ prefix.to_u128();
+        match len {
+            0 => true,
+            len if len >= Self::BITS => diff == 0,
+            len => diff >> (Self::BITS - len) == 0,
+        }
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, WireSize)]
 #[repr(C)]
 pub enum Command {
-    Set(Id, Message, DevId),
+    /// Handshake, answered by `Response::Hello`, kept first so its encoding never changes
+    Hello(Hello, DevId),
+    /// Write parameter 0 of `Id`, other parameters cannot be set by the master
+    Set(Id, Value, DevId),
     Get(Id, Parameter, DevId),
+    /// Probe for a servant, answered by `Response::Pong`
+    Ping(DevId),
+    /// Keepalive, answered by `Response::Heartbeat`
+    Heartbeat(DevId),
+    /// Unassigned servants whose `Uid` starts with the first `u8` bits of `Uid` answer
+    /// by `Response::Claim`, addressed to `UNASSIGNED`
+    Claim(Uid, u8, DevId),
+    /// The servant with `Uid` adopts the `DevId` (the first one), answered by
+    /// `Response::SetOk`, addressed to `UNASSIGNED`
+    Assign(Uid, DevId, DevId),
+    /// Sub-commands executed in order, answered by `Response::Batch`
+    Batch(heapless::Vec<Op, BATCH>, DevId),
+    /// Push the value of a parameter by `Response::Notify`, see `Subscriptions`
+    Subscribe {
+        id: Id,
+        parameter: Parameter,
+        period_ms: u32,
+        deadband: f32,
+        dev: DevId,
+    },
+    Unsubscribe {
+        id: Id,
+        parameter: Parameter,
+        dev: DevId,
+    },
+    /// Sent at master time `t1`, answered by `Response::TimeSync`, see `Clock`
+    TimeSync {
+        t1: Micros,
+        dev: DevId,
+    },
+    /// The servant clock is ahead of the master clock by `offset` microseconds,
+    /// answered by `Response::SetOk`
+    ClockOffset {
+        offset: i64,
+        dev: DevId,
+    },
+    /// Set parameter 0 of `id` at the synchronised time `at`, see `Schedule`
+    SetAt {
+        id: Id,
+        value: Value,
+        at: Micros,
+        dev: DevId,
+    },
+    /// Set parameter 0 of `Id` on the next `Command::Trigger`
+    Stage(Id, Value, DevId),
+    /// Apply the staged values, typically broadcast
+    Trigger(DevId),
+    /// Start (or resume) the update to an image of `size` bytes, answered by
+    /// `Response::UpdateReady`, see `Updater`
+    UpdateBegin {
+        size: u32,
+        digest: Digest,
+        dev: DevId,
+    },
+    /// The `index`th `CHUNK` of the image (the last one may be shorter), `crc` of `data`
+    UpdateChunk {
+        index: u32,
+        data: heapless::Vec<u8, CHUNK>,
+        crc: u32,
+        dev: DevId,
+    },
+    /// Check the digest of the received image
+    UpdateVerify(DevId),
+    /// Boot the verified image
+    UpdateCommit(DevId),
+    /// Discard the update, refused (`ErrorCode::InvalidState`) once committed
+    UpdateAbort(DevId),
+    /// Open `blob` for reading, or for writing `size` bytes, answered by
+    /// `Response::BlobOpened`, see `Blobs`
+    BlobOpen {
+        blob: BlobId,
+        size: Option<u32>,
+        dev: DevId,
+    },
+    /// Read the open blob at `offset`, answered by `Response::BlobData`
+    BlobRead {
+        offset: u32,
+        dev: DevId,
+    },
+    /// Write `data` at `offset` of the blob open for writing
+    BlobWrite {
+        offset: u32,
+        data: heapless::Vec<u8, BLOB_CHUNK>,
+        dev: DevId,
+    },
+    /// Checksum the contents of the open blob, answered by `Response::BlobChecksum`
+    BlobChecksum(DevId),
+    /// Close the open blob, completing a write
+    BlobClose(DevId),
+    /// Propose fragments of `mtu` bytes, answered by `Response::Mtu`, see `Messages`
+    Mtu {
+        mtu: u16,
+        dev: DevId,
+    },
+    /// Fragment `index` of `count` of message `msg`, answered by `Response::SetOk`, or
+    /// `Response::Message` once the message is complete
+    Fragment {
+        msg: MsgId,
+        index: u16,
+        count: u16,
+        data: heapless::Vec<u8, FRAGMENT>,
+        dev: DevId,
+    },
+    /// Read fragment `index` of the reply to message `msg`, answered by `Response::Fragment`
+    FragmentRead {
+        msg: MsgId,
+        index: u16,
+        dev: DevId,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[repr(C)]
-pub enum Message {
-    A,
-    B(u32),
-    C(f32), // we might consider "f16" but not sure it plays well with `ssmarshal`
+impl Command {
+    /// The addressed servant
+    pub fn dev(&self) -> DevId {
+        match self {
+            Command::Hello(_, dev)
+            | Command::Set(_, _, dev)
+            | Command::Get(_, _, dev)
+            | Command::Ping(dev)
+            | Command::Heartbeat(dev)
+            | Command::Claim(_, _, dev)
+            | Command::Assign(_, _, dev)
+            | Command::Batch(_, dev)
+            | Command::Subscribe { dev, .. }
+            | Command::Unsubscribe { dev, .. }
+            | Command::TimeSync { dev, .. }
+            | Command::ClockOffset { dev, .. }
+            | Command::SetAt { dev, .. }
+            | Command::Stage(_, _, dev)
+            | Command::Trigger(dev)
+            | Command::UpdateBegin { dev, .. }
+            | Command::UpdateChunk { dev, .. }
+            | Command::UpdateVerify(dev)
+            | Command::UpdateCommit(dev)
+            | Command::UpdateAbort(dev)
+            | Command::BlobOpen { dev, .. }
+            | Command::BlobRead { dev, .. }
+            | Command::BlobWrite { dev, .. }
+            | Command::BlobChecksum(dev)
+            | Command::BlobClose(dev)
+            | Command::Mtu { dev, .. }
+            | Command::Fragment { dev, .. }
+            | Command::FragmentRead { dev, .. } => *dev,
+        }
+    }
+
+    /// The command addressed to `dev`
+    pub fn with_dev(mut self, dev: DevId) -> Self {
+        match &mut self {
+            Command::Hello(_, d)
+            | Command::Set(_, _, d)
+            | Command::Get(_, _, d)
+            | Command::Ping(d)
+            | Command::Heartbeat(d)
+            | Command::Claim(_, _, d)
+            | Command::Assign(_, _, d)
+            | Command::Batch(_, d)
+            | Command::Subscribe { dev: d, .. }
+            | Command::Unsubscribe { dev: d, .. }
+            | Command::TimeSync { dev: d, .. }
+            | Command::ClockOffset { dev: d, .. }
+            | Command::SetAt { dev: d, .. }
+            | Command::Stage(_, _, d)
+            | Command::Trigger(d)
+            | Command::UpdateBegin { dev: d, .. }
+            | Command::UpdateChunk { dev: d, .. }
+            | Command::UpdateVerify(d)
+            | Command::UpdateCommit(d)
+            | Command::UpdateAbort(d)
+            | Command::BlobOpen { dev: d, .. }
+            | Command::BlobRead { dev: d, .. }
+            | Command::BlobWrite { dev: d, .. }
+            | Command::BlobChecksum(d)
+            | Command::BlobClose(d)
+            | Command::Mtu { dev: d, .. }
+            | Command::Fragment { dev: d, .. }
+            | Command::FragmentRead { dev: d, .. } => *d = dev,
+        }
+        self
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, WireSize)]
 #[repr(C)]
 pub enum Response {
-    Data(Id, Parameter, u32, DevId),
+    /// Handshake of the servant, kept first so its encoding never changes
+    Hello(Hello),
+    Data(Id, Parameter, Value, DevId),
     SetOk,
-    ParseError,
+    /// The command failed, `u32` gives details depending on the code
+    Error(ErrorCode, u32),
+    Pong(Info),
+    Claim(Uid),
+    /// The outcomes of the sub-commands of a `Command::Batch`, in order
+    Batch(heapless::Vec<Outcome, BATCH>),
+    /// Unsolicited value of a subscribed parameter, sampled at the servant time `Micros`
+    Notify(Id, Parameter, Value, Micros),
+    /// The servant is alive, in reply to `Command::Heartbeat` or unsolicited
+    Heartbeat(Session),
+    /// `t1` echoed, the servant times the request was received (`t2`) and the reply sent (`t3`)
+    TimeSync {
+        t1: Micros,
+        t2: Micros,
+        t3: Micros,
+    },
+    /// Bytes of the image received so far, the upload continues from there
+    UpdateReady {
+        received: u32,
+    },
+    /// Size of the blob opened
+    BlobOpened {
+        size: u32,
+    },
+    /// Up to `BLOB_CHUNK` bytes read, fewer at the end of the blob
+    BlobData(heapless::Vec<u8, BLOB_CHUNK>),
+    /// crc (`CKSUM`) of the blob contents
+    BlobChecksum(u32),
+    /// The fragment size agreed on and the largest message accepted
+    Mtu(Mtu),
+    /// Message `msg` was handled, its reply has `size` bytes
+    Message {
+        msg: MsgId,
+        size: u32,
+    },
+    /// Fragment `index` of `count` of the reply to message `msg`
+    Fragment {
+        msg: MsgId,
+        index: u16,
+        count: u16,
+        data: heapless::Vec<u8, FRAGMENT>,
+    },
+}
+
+/// A sub-command of `Command::Batch`, addressed to the servant of the batch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, WireSize)]
+#[repr(C)]
+pub enum Op {
+    Get(Id, Parameter),
+    Set(Id, Value),
+}
+
+/// Outcome of an `Op`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, WireSize)]
+#[repr(C)]
+pub enum Outcome {
+    Data(Id, Parameter, Value),
+    SetOk,
+    Error(ErrorCode, u32),
+}
+
+/// Reason of a `Response::Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, WireSize)]
+#[repr(C)]
+pub enum ErrorCode {
+    /// No parameter declared for the `Id` (detail: the `Id`)
+    UnknownId,
+    /// The `Id` has no such `Parameter` (detail: the `Parameter`)
+    UnknownParameter,
+    /// `Set` of a read-only parameter (detail: the `Id`)
+    ReadOnly,
+    /// `Get` of a write-only parameter (detail: the `Id`)
+    WriteOnly,
+    /// The value was rejected by the servant (detail: the `Id`)
+    OutOfRange,
+    /// The value is not of the parameter type (detail: the `Id`)
+    TypeMismatch,
+    /// The servant cannot serve the request now, try again later (detail: the `Id`)
+    Busy,
+    /// The request frame failed the crc check (detail: the crc carried by the frame)
+    CrcError,
+    /// The request frame could not be decoded
+    Malformed,
+    /// The command is addressed to another servant (detail: the `DevId` of the servant
+    /// answering), see `Servant::with_error_replies`
+    NotAddressed,
+    /// No room to queue a scheduled or staged value (detail: the `Id`)
+    QueueFull,
+    /// The time of a scheduled value has passed (detail: the `Id`)
+    TooLate,
+    /// A chunk was skipped (detail: the index of the chunk expected)
+    OutOfOrder,
+    /// The command is not valid in the current state, e.g., a chunk before `UpdateBegin`
+    InvalidState,
+    /// The received image does not match its digest
+    VerifyFailed,
+    /// Writing or reading the staging area failed (detail: the chunk index or offset)
+    Storage,
+    /// The image carries no signature
+    Unsigned,
+    /// The image signature does not match, it was altered or signed by another key
+    BadSignature,
+    /// The image is older than the installed firmware (detail: its version, `0x00MMmmpp`)
+    Downgrade,
+    /// The command is not handled by the servant, e.g., a feature it lacks
+    Unsupported,
+}
+
+impl core::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::UnknownId => "unknown id",
+            Self::UnknownParameter => "unknown parameter",
+            Self::ReadOnly => "parameter is read-only",
+            Self::WriteOnly => "parameter is write-only",
+            Self::OutOfRange => "value out of range",
+            Self::TypeMismatch => "value of wrong type",
+            Self::Busy => "servant busy",
+            Self::CrcError => "request failed crc check",
+            Self::Malformed => "request could not be decoded",
+            Self::NotAddressed => "command addressed to another servant",
+            Self::QueueFull => "schedule queue full",
+            Self::TooLate => "scheduled time has passed",
+            Self::OutOfOrder => "chunk out of order",
+            Self::InvalidState => "command not valid in the current state",
+            Self::VerifyFailed => "image does not match its digest",
+            Self::Storage => "staging area failed",
+            Self::Unsigned => "image is not signed",
+            Self::BadSignature => "image signature does not match",
+            Self::Downgrade => "image is older than the installed firmware",
+            Self::Unsupported => "command not handled by the servant",
+        })
+    }
+}
+
+/// Firmware version of a servant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, WireSize)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+/// Features supported by a servant, a set of flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, WireSize)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    /// The servant takes part in multicast (single bit `DevId`)
+    pub const MULTICAST: Self = Self(1 << 0);
+    /// `Command::Batch`
+    pub const BATCH: Self = Self(1 << 1);
+    /// Subscriptions (`Command::Subscribe`)
+    pub const TELEMETRY: Self = Self(1 << 2);
+    /// Blobs and messages larger than a frame
+    pub const BULK: Self = Self(1 << 3);
+    /// Only signed firmware images are accepted
+    pub const AUTH: Self = Self(1 << 4);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl core::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let names = [
+            (Self::MULTICAST, "multicast"),
+            (Self::BATCH, "batch"),
+            (Self::TELEMETRY, "telemetry"),
+            (Self::BULK, "bulk"),
+            (Self::AUTH, "auth"),
+        ];
+        let mut sep = "";
+        for (flag, name) in names {
+            if self.contains(flag) {
+                write!(f, "{}{}", sep, name)?;
+                sep = ", ";
+            }
+        }
+        if sep.is_empty() {
+            f.write_str("none")?;
+        }
+        Ok(())
+    }
+}
+
+/// Version of the protocol, incremented when the meaning of commands changes
+pub const PROTOCOL: u16 = 1;
+
+/// Hash of the encoding of `Request` and `Reply` (and so `Command` and `Response`)
+pub const SCHEMA: u32 = schema_mix(Request::SCHEMA, Reply::SCHEMA);
+
+/// Protocol parameters, exchanged by `Command::Hello` and `Response::Hello`
+///
+/// Its encoding must not change, so peers built from different definitions can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, WireSize)]
+pub struct Hello {
+    pub protocol: u16,
+    pub schema: u32,
+    /// Largest frame received
+    pub max_frame: u16,
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    /// The parameters of this build, receiving frames of up to `max_frame` bytes
+    pub const fn new(max_frame: u16, capabilities: Capabilities) -> Self {
+        Self {
+            protocol: PROTOCOL,
+            schema: SCHEMA,
+            max_frame,
+            capabilities,
+        }
+    }
+
+    /// Whether `other` speaks the same protocol, whatever its capabilities
+    pub fn compatible(&self, other: &Hello) -> bool {
+        (self.protocol, self.schema) == (other.protocol, other.schema)
+    }
+}
+
+/// Servant identification, reported by `Response::Pong`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, WireSize)]
+pub struct Info {
+    pub version: Version,
+    pub capabilities: Capabilities,
+    /// Size of the receive window (`Servant<W>`)
+    pub window: u8,
+}
+
+/// Message sizes of a servant, reported by `Response::Mtu`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, WireSize)]
+pub struct Mtu {
+    /// Bytes per fragment
+    pub fragment: u16,
+    /// Largest message
+    pub message: u32,
+}
+
+/// Identifies a run of the servant firmware, reported by `Response::Heartbeat`
+///
+/// `boot` counts the resets (kept in non-volatile memory by the application), `id` is
+/// drawn at random at each boot, so resets are detected even without a boot counter.
+/// Any change of the session means the servant was reset and lost its volatile state
+/// (e.g., subscriptions).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, WireSize)]
+pub struct Session {
+    pub boot: u32,
+    pub id: u32,
+}
+
+/// A `Command` tagged with a sequence number, sent by the master
+///
+/// The command is addressed to the servant with its `DevId`, or, if `multicast`, to the
+/// servants whose (single bit) `DevId` is set in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, WireSize)]
+pub struct Request {
+    pub seq: Seq,
+    pub multicast: bool,
+    pub cmd: Command,
+}
+
+/// A `Response` echoing the sequence number of the `Request`, sent by the servant `dev`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, WireSize)]
+pub struct Reply {
+    pub seq: Seq,
+    pub dev: DevId,
+    pub resp: Response,
 }
 
 pub const CKSUM: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_CKSUM);
 
+/// Errors raised when framing or unframing data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The output buffer cannot hold the serialized payload, crc and cobs overhead
+    BufferTooSmall,
+    /// The payload could not be serialized
+    Serialize,
+    /// The input is not a valid cobs frame
+    CobsDecode,
+    /// The payload (or crc) could not be deserialized
+    Deserialize,
+    /// `expected` is the crc carried by the frame, `actual` the crc computed on the payload
+    CrcMismatch { expected: u32, actual: u32 },
+    /// The decoded frame holds additional bytes after the crc
+    TrailingBytes,
+    /// The frame holds no data
+    EmptyFrame,
+    /// The frame did not fit the receive buffer and was discarded
+    Overflow,
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::BufferTooSmall => f.write_str("buffer too small"),
+            Self::Serialize => f.write_str("serialization failed"),
+            Self::CobsDecode => f.write_str("cobs decoding failed"),
+            Self::Deserialize => f.write_str("deserialization failed"),
+            Self::CrcMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "crc mismatch, expected {:#010x}, actual {:#010x}",
+                    expected, actual
+                )
+            }
+            Self::TrailingBytes => f.write_str("trailing bytes after crc"),
+            Self::EmptyFrame => f.write_str("empty frame"),
+            Self::Overflow => f.write_str("frame overflow"),
+        }
+    }
+}
+
 /// Serialize T into cobs encoded out_buf with crc
-/// panics on all errors
-/// TODO: reasonable error handling
-pub fn serialize_crc_cobs<'a, T: serde::Serialize, const N: usize>(
+///
+/// The payload is serialized at a small offset and cobs encoded in place,
+/// so no copy of the buffer is made. `out_buf` must hold the largest frame of T
+/// (see `Frame::frame_buf`).
+pub fn serialize_crc_cobs<'a, T: serde::Serialize + WireSize, const N: usize>(
     t: &T,
     out_buf: &'a mut [u8; N],
-) -> &'a [u8] {
-    let n_ser = ssmarshal::serialize(out_buf, t).unwrap();
-    let crc = CKSUM.checksum(&out_buf[0..n_ser]);
-    let n_crc = ssmarshal::serialize(&mut out_buf[n_ser..], &crc).unwrap();
-    let buf_copy = *out_buf; // implies memcpy, could we do better?
-    let n = corncobs::encode_buf(&buf_copy[0..n_ser + n_crc], out_buf);
-    &out_buf[0..n]
-}
-
-/// deserialize T from cobs in_buf with crc check
-/// panics on all errors
-/// TODO: reasonable error handling
-pub fn deserialize_crc_cobs<T>(in_buf: &mut [u8]) -> Result<T, ()>
+) -> Result<&'a [u8], FrameError> {
+    let start = encode::head_room(N);
+    // checked up front, ssmarshal (debug) asserts rather than fail on running out of space
+    if start + T::MAX_WIRE_SIZE + core::mem::size_of::<u32>() > N {
+        return Err(FrameError::BufferTooSmall);
+    }
+    let n_ser = ssmarshal::serialize(&mut out_buf[start..], t).map_err(ser_error)?;
+    let crc = CKSUM.checksum(&out_buf[start..start + n_ser]);
+    let n_crc = ssmarshal::serialize(&mut out_buf[start + n_ser..], &crc).map_err(ser_error)?;
+    if corncobs::max_encoded_len(n_ser + n_crc) > N {
+        return Err(FrameError::BufferTooSmall);
+    }
+    let n = encode::encode_in_place(out_buf, start, n_ser + n_crc);
+    Ok(&out_buf[0..n])
+}
+
+fn ser_error(err: ssmarshal::Error) -> FrameError {
+    match err {
+        ssmarshal::Error::EndOfStream => FrameError::BufferTooSmall,
+        _ => FrameError::Serialize,
+    }
+}
+
+/// Deserialize T from cobs in_buf with crc check
+pub fn deserialize_crc_cobs<T>(in_buf: &mut [u8]) -> Result<T, FrameError>
 where
     T: for<'de> serde::Deserialize<'de>,
 {
-    let n = corncobs::decode_in_place(in_buf).unwrap();
-    let (t, resp_used) = ssmarshal::deserialize::<T>(&in_buf[0..n]).unwrap();
-    let crc_buf = &in_buf[resp_used..];
-    let (crc, _crc_used) = ssmarshal::deserialize::<u32>(crc_buf).unwrap();
-    let pkg_crc = CKSUM.checksum(&in_buf[0..resp_used]);
-    assert_eq! {crc, pkg_crc};
+    if matches!(in_buf.first(), None | Some(&corncobs::ZERO)) {
+        return Err(FrameError::EmptyFrame);
+    }
+    let n = corncobs::decode_in_place(in_buf).map_err(|_| FrameError::CobsDecode)?;
+    if n == 0 {
+        return Err(FrameError::EmptyFrame);
+    }
+    // the crc is carried by the last bytes of the frame
+    let crc_size = core::mem::size_of::<u32>();
+    if n < crc_size {
+        return Err(FrameError::Deserialize);
+    }
+    let (payload, crc_buf) = in_buf[0..n].split_at(n - crc_size);
+    let (crc, _crc_used) = decode::from_bytes::<u32>(crc_buf)?;
+    let pkg_crc = CKSUM.checksum(payload);
+    if crc != pkg_crc {
+        return Err(FrameError::CrcMismatch {
+            expected: crc,
+            actual: pkg_crc,
+        });
+    }
+    let (t, resp_used) = decode::from_bytes::<T>(payload)?;
+    if resp_used != payload.len() {
+        return Err(FrameError::TrailingBytes);
+    }
     Ok(t)
 }
+
+/// Accumulates a byte stream into cobs frames of at most N bytes (including the delimiter)
+///
+/// Frames exceeding N bytes are discarded up to the next delimiter and reported as
+/// `FrameError::Overflow`. Garbage on the line yields a single error for the broken frame,
+/// after which the accumulator is in sync again. Empty frames (repeated delimiters) are ignored.
+pub struct FrameAccumulator<const N: usize> {
+    buf: [u8; N],
+    index: usize,
+    overflow: bool,
+}
+
+impl<const N: usize> Default for FrameAccumulator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> FrameAccumulator<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            index: 0,
+            overflow: false,
+        }
+    }
+
+    /// Drop any partially received frame
+    pub fn reset(&mut self) {
+        self.index = 0;
+        self.overflow = false;
+    }
+
+    /// Push a single byte, returns the decoded frame on a delimiter
+    pub fn push<T>(&mut self, byte: u8) -> Option<Result<T, FrameError>>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        if byte != corncobs::ZERO {
+            if self.index < N {
+                self.buf[self.index] = byte;
+                self.index += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        // end of cobs frame
+        let (index, overflow) = (self.index, self.overflow);
+        self.reset();
+        if overflow || index == N {
+            // no room left for the delimiter either
+            return Some(Err(FrameError::Overflow));
+        }
+        if index == 0 {
+            return None;
+        }
+        self.buf[index] = corncobs::ZERO;
+        Some(deserialize_crc_cobs(&mut self.buf[0..index + 1]))
+    }
+
+    /// Push bytes until a frame is completed, returns the frame (if any) and the unconsumed bytes
+    pub fn push_slice<'a, T>(
+        &mut self,
+        bytes: &'a [u8],
+    ) -> (Option<Result<T, FrameError>>, &'a [u8])
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        for (i, byte) in bytes.iter().enumerate() {
+            if let Some(frame) = self.push(*byte) {
+                return (Some(frame), &bytes[i + 1..]);
+            }
+        }
+        (None, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame `payload` with a valid crc
+    fn frame(payload: &[u8], out: &mut [u8]) -> usize {
+        let mut raw = [0u8; 512];
+        raw[..payload.len()].copy_from_slice(payload);
+        let crc = CKSUM.checksum(payload).to_le_bytes();
+        raw[payload.len()..payload.len() + 4].copy_from_slice(&crc);
+        corncobs::encode_buf(&raw[..payload.len() + 4], out)
+    }
+
+    fn request() -> Request {
+        Request {
+            seq: 7,
+            multicast: false,
+            cmd: Command::BlobWrite {
+                offset: 64,
+                data: (1..=20).collect(),
+                dev: 2,
+            },
+        }
+    }
+
+    /// Frame the request into `out`, returns its length
+    fn framed(req: &Request, out: &mut [u8]) -> usize {
+        let mut buf = Request::frame_buf();
+        let bytes = serialize_crc_cobs(req, &mut buf).unwrap();
+        out[..bytes.len()].copy_from_slice(bytes);
+        bytes.len()
+    }
+
+    type Acc = FrameAccumulator<{ Request::MAX_FRAME_SIZE }>;
+
+    #[test]
+    fn accumulator_overflow_resyncs() {
+        let mut acc = Acc::new();
+        for _ in 0..Request::MAX_FRAME_SIZE + 10 {
+            assert_eq!(acc.push::<Request>(0x55), None);
+        }
+        assert_eq!(acc.push::<Request>(0), Some(Err(FrameError::Overflow)));
+        // in sync again at the next delimiter
+        let mut buf = [0u8; 256];
+        let n = framed(&request(), &mut buf);
+        assert_eq!(acc.push_slice(&buf[..n]), (Some(Ok(request())), &[][..]));
+    }
+
+    #[test]
+    fn accumulator_overflow_by_delimiter() {
+        // exactly N bytes leave no room for the delimiter
+        let mut acc = FrameAccumulator::<8>::new();
+        for _ in 0..8 {
+            assert_eq!(acc.push::<u32>(1), None);
+        }
+        assert_eq!(acc.push::<u32>(0), Some(Err(FrameError::Overflow)));
+    }
+
+    #[test]
+    fn accumulator_garbage_yields_single_error() {
+        let mut acc = Acc::new();
+        let mut buf = [0u8; 256];
+        buf[..3].copy_from_slice(&[0x12, 0x34, 0x56]);
+        let n = framed(&request(), &mut buf[4..]) + 4;
+        // garbage, a delimiter, then a frame
+        buf[3] = 0;
+        let (first, rest) = acc.push_slice::<Request>(&buf[..n]);
+        assert!(matches!(first, Some(Err(_))));
+        assert_eq!(acc.push_slice(rest), (Some(Ok(request())), &[][..]));
+    }
+
+    #[test]
+    fn accumulator_ignores_empty_frames() {
+        let mut acc = Acc::new();
+        let mut buf = [0u8; 256];
+        let n = framed(&request(), &mut buf[3..]) + 3;
+        let (frame, rest) = acc.push_slice::<Request>(&buf[..n]);
+        assert_eq!(frame, Some(Ok(request())));
+        assert!(rest.is_empty());
+        assert_eq!(acc.push_slice::<Request>(&[0, 0, 0]), (None, &[][..]));
+    }
+
+    #[test]
+    fn accumulator_back_to_back_frames() {
+        let mut acc = Acc::new();
+        let mut other = request();
+        other.seq = 8;
+        let mut buf = [0u8; 512];
+        let n = framed(&request(), &mut buf);
+        let m = framed(&other, &mut buf[n..]) + n;
+        let (first, rest) = acc.push_slice::<Request>(&buf[..m]);
+        assert_eq!(first, Some(Ok(request())));
+        assert_eq!(rest.len(), m - n);
+        assert_eq!(acc.push_slice(rest), (Some(Ok(other)), &[][..]));
+    }
+
+    #[test]
+    fn accumulator_frame_split_across_pushes() {
+        let mut buf = [0u8; 256];
+        let n = framed(&request(), &mut buf);
+        for split in 1..n {
+            let mut acc = Acc::new();
+            assert_eq!(acc.push_slice::<Request>(&buf[..split]), (None, &[][..]));
+            assert_eq!(
+                acc.push_slice(&buf[split..n]),
+                (Some(Ok(request())), &[][..])
+            );
+        }
+        // byte by byte
+        let mut acc = Acc::new();
+        for (i, byte) in buf[..n].iter().enumerate() {
+            let frame = acc.push::<Request>(*byte);
+            if i + 1 < n {
+                assert_eq!(frame, None);
+            } else {
+                assert_eq!(frame, Some(Ok(request())));
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut out = Request::frame_buf();
+        let n = serialize_crc_cobs(&request(), &mut out).unwrap().len();
+        assert_eq!(
+            deserialize_crc_cobs::<Request>(&mut out[..n]),
+            Ok(request())
+        );
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let mut out = [0u8; 16];
+        assert_eq!(
+            serialize_crc_cobs(&request(), &mut out),
+            Err(FrameError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let mut payload = [0u8; 128];
+        let used = ssmarshal::serialize(&mut payload, &request()).unwrap();
+        for len in 0..used {
+            let mut buf = [0u8; 256];
+            let n = frame(&payload[..len], &mut buf);
+            let result = deserialize_crc_cobs::<Request>(&mut buf[..n]);
+            assert!(
+                matches!(
+                    result,
+                    Err(FrameError::Deserialize | FrameError::EmptyFrame)
+                ),
+                "{} bytes: {:?}",
+                len,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn payload_of_other_type() {
+        let reply = Reply {
+            seq: 3,
+            dev: 1,
+            resp: Response::Error(ErrorCode::CrcError, 0),
+        };
+        let mut out = Reply::frame_buf();
+        let n = serialize_crc_cobs(&reply, &mut out).unwrap().len();
+        assert!(deserialize_crc_cobs::<Request>(&mut out[..n]).is_err());
+        // a sequence length beyond the end of the payload (seq, multicast, tag and offset
+        // precede it)
+        let mut payload = [0u8; 128];
+        let used = ssmarshal::serialize(&mut payload, &request()).unwrap();
+        payload[8..16].copy_from_slice(&[0xff; 8]);
+        let mut buf = [0u8; 256];
+        let n = frame(&payload[..used], &mut buf);
+        assert_eq!(
+            deserialize_crc_cobs::<Request>(&mut buf[..n]),
+            Err(FrameError::Deserialize)
+        );
+    }
+}
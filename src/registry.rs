@@ -0,0 +1,363 @@
+//! Servant side parameter storage and `Get`/`Set` dispatch
+//!
+//! A `ParameterRegistry` holds a fixed set of `Slot`s, each storing a typed value (the
+//! `Value` variant given at declaration) for an `Id`/`Parameter` pair. `dispatch`
+//! executes `Command::Get`/`Command::Set` against the slots and builds the `Response`,
+//! so it can be passed to `Servant::handle` as is.
+//!
+//! `Command::Set` (and `Op::Set`) carries no `Parameter`, it writes parameter 0 of the
+//! `Id`: other parameters are read-only to the master, whatever their `Access`, and only
+//! set by the application (`store`).
+//!
+//! Requests that cannot be served are answered by `Response::Error`, the callbacks may
+//! fail with their own `ErrorCode` (e.g., `OutOfRange` or `Busy`).
+
+use crate::{Command, ErrorCode, Id, Op, Outcome, Parameter, Response, Value};
+use core::mem::discriminant;
+
+/// Access granted to the master
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl Access {
+    pub const fn readable(self) -> bool {
+        matches!(self, Access::ReadOnly | Access::ReadWrite)
+    }
+
+    pub const fn writable(self) -> bool {
+        matches!(self, Access::WriteOnly | Access::ReadWrite)
+    }
+}
+
+/// Called before a value is read, e.g., to sample a sensor into the value
+pub type ReadHook = fn(&mut Value) -> Result<(), ErrorCode>;
+
+/// Called with a new value before it is stored, an error rejects it
+pub type WriteHook = fn(&Value) -> Result<(), ErrorCode>;
+
+/// A parameter, its access flags and stored value
+#[derive(Debug, Clone, Copy)]
+pub struct Slot {
+    id: Id,
+    parameter: Parameter,
+    access: Access,
+    value: Value,
+    on_read: Option<ReadHook>,
+    on_write: Option<WriteHook>,
+}
+
+impl Slot {
+    /// A parameter holding `value`, its variant is the type accepted by `Set`
+    pub const fn new(id: Id, parameter: Parameter, access: Access, value: Value) -> Self {
+        Self {
+            id,
+            parameter,
+            access,
+            value,
+            on_read: None,
+            on_write: None,
+        }
+    }
+
+    /// Called before the value is read, e.g., to sample a sensor into the value
+    pub const fn on_read(mut self, f: ReadHook) -> Self {
+        self.on_read = Some(f);
+        self
+    }
+
+    /// Called with the new value before it is stored, an error rejects it
+    pub const fn on_write(mut self, f: WriteHook) -> Self {
+        self.on_write = Some(f);
+        self
+    }
+
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    pub fn parameter(&self) -> Parameter {
+        self.parameter
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    /// The stored value
+    pub fn value(&self) -> Value {
+        self.value
+    }
+}
+
+/// A fixed set of `N` parameters
+pub struct ParameterRegistry<const N: usize> {
+    slots: [Slot; N],
+}
+
+impl<const N: usize> ParameterRegistry<N> {
+    pub const fn new(slots: [Slot; N]) -> Self {
+        Self { slots }
+    }
+
+    /// The declared parameters
+    pub fn slots(&self) -> &[Slot; N] {
+        &self.slots
+    }
+
+    /// The slot of `id`/`parameter`, if declared
+    pub fn slot(&self, id: Id, parameter: Parameter) -> Option<&Slot> {
+        self.slots
+            .iter()
+            .find(|s| s.id == id && s.parameter == parameter)
+    }
+
+    fn slot_mut(&mut self, id: Id, parameter: Parameter) -> Option<&mut Slot> {
+        self.slots
+            .iter_mut()
+            .find(|s| s.id == id && s.parameter == parameter)
+    }
+
+    fn find(&self, id: Id, parameter: Parameter) -> Result<&Slot, (ErrorCode, u32)> {
+        if !self.slots.iter().any(|s| s.id == id) {
+            return Err((ErrorCode::UnknownId, id));
+        }
+        self.slot(id, parameter)
+            .ok_or((ErrorCode::UnknownParameter, parameter))
+    }
+
+    fn lookup(&mut self, id: Id, parameter: Parameter) -> Result<&mut Slot, (ErrorCode, u32)> {
+        self.find(id, parameter)?;
+        Ok(self.slot_mut(id, parameter).unwrap())
+    }
+
+    /// Check `Command::Get` of `id`/`parameter` as `read` does, without reading it (e.g.,
+    /// before subscribing to it)
+    pub fn check_read(&self, id: Id, parameter: Parameter) -> Result<(), (ErrorCode, u32)> {
+        if !self.find(id, parameter)?.access.readable() {
+            return Err((ErrorCode::WriteOnly, id));
+        }
+        Ok(())
+    }
+
+    /// The value of `id`/`parameter` if readable by the master, without calling `on_read`
+    /// (e.g., for `Subscriptions::poll`, the application keeping it current by `store`)
+    pub fn sample(&self, id: Id, parameter: Parameter) -> Option<Value> {
+        self.slot(id, parameter)
+            .filter(|slot| slot.access.readable())
+            .map(|slot| slot.value)
+    }
+
+    /// Check `value` as `Command::Set` of `id` does, without storing it
+    pub fn check(&self, id: Id, value: &Value) -> Result<(), (ErrorCode, u32)> {
+        let slot = self.find(id, 0)?;
+        if !slot.access.writable() {
+            return Err((ErrorCode::ReadOnly, id));
+        }
+        if discriminant(&slot.value) != discriminant(value) {
+            return Err((ErrorCode::TypeMismatch, id));
+        }
+        if let Some(f) = slot.on_write {
+            f(value).map_err(|code| (code, id))?;
+        }
+        Ok(())
+    }
+
+    /// Set the value of `id`/`parameter` from the application side, ignoring access flags
+    /// and callbacks, returns `false` if not declared or of another type
+    pub fn store(&mut self, id: Id, parameter: Parameter, value: Value) -> bool {
+        match self.slot_mut(id, parameter) {
+            Some(slot) if discriminant(&slot.value) == discriminant(&value) => {
+                slot.value = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Execute `cmd` against the parameters, returns the response to send
+    ///
+    /// The operations of a `Command::Batch` are executed in order, a failing operation
+    /// does not affect the others.
+    pub fn dispatch(&mut self, cmd: &Command) -> Response {
+        match *cmd {
+            Command::Get(id, parameter, dev) => match self.read(id, parameter) {
+                Ok(value) => Response::Data(id, parameter, value, dev),
+                Err((code, detail)) => Response::Error(code, detail),
+            },
+            Command::Set(id, value, _dev) => match self.write(id, value) {
+                Ok(()) => Response::SetOk,
+                Err((code, detail)) => Response::Error(code, detail),
+            },
+            Command::Batch(ref ops, _dev) => {
+                Response::Batch(ops.iter().map(|op| self.execute(op)).collect())
+            }
+            _ => Response::Error(ErrorCode::Unsupported, 0),
+        }
+    }
+
+    /// Execute a single operation of a batch
+    pub fn execute(&mut self, op: &Op) -> Outcome {
+        let result = match *op {
+            Op::Get(id, parameter) => self
+                .read(id, parameter)
+                .map(|value| Outcome::Data(id, parameter, value)),
+            Op::Set(id, value) => self.write(id, value).map(|()| Outcome::SetOk),
+        };
+        result.unwrap_or_else(|(code, detail)| Outcome::Error(code, detail))
+    }
+
+    /// Read the value of `id`/`parameter` as `Command::Get` does, calling `on_read`
+    pub fn read(&mut self, id: Id, parameter: Parameter) -> Result<Value, (ErrorCode, u32)> {
+        let slot = self.lookup(id, parameter)?;
+        if !slot.access.readable() {
+            return Err((ErrorCode::WriteOnly, id));
+        }
+        if let Some(f) = slot.on_read {
+            f(&mut slot.value).map_err(|code| (code, id))?;
+        }
+        Ok(slot.value)
+    }
+
+    fn write(&mut self, id: Id, value: Value) -> Result<(), (ErrorCode, u32)> {
+        self.check(id, &value)?;
+        self.lookup(id, 0)?.value = value;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV: u32 = 0b001;
+
+    /// A counter incremented on each read and a setpoint in [0, 1]
+    fn registry() -> ParameterRegistry<4> {
+        ParameterRegistry::new([
+            Slot::new(0x10, 0, Access::ReadOnly, Value::U32(0)).on_read(|value| {
+                if let Value::U32(n) = value {
+                    *n += 1;
+                }
+                Ok(())
+            }),
+            Slot::new(0x10, 1, Access::ReadOnly, Value::Bool(true)),
+            Slot::new(0x11, 0, Access::WriteOnly, Value::I32(0)),
+            Slot::new(0x12, 0, Access::ReadWrite, Value::F32(0.0)).on_write(|value| match value {
+                Value::F32(v) if (0.0..=1.0).contains(v) => Ok(()),
+                _ => Err(ErrorCode::OutOfRange),
+            }),
+        ])
+    }
+
+    #[test]
+    fn access_flags() {
+        let mut registry = registry();
+        let set = Command::Set(0x10, Value::U32(5), DEV);
+        assert_eq!(
+            registry.dispatch(&set),
+            Response::Error(ErrorCode::ReadOnly, 0x10)
+        );
+        let get = Command::Get(0x11, 0, DEV);
+        assert_eq!(
+            registry.dispatch(&get),
+            Response::Error(ErrorCode::WriteOnly, 0x11)
+        );
+        let set = Command::Set(0x11, Value::I32(3), DEV);
+        assert_eq!(registry.dispatch(&set), Response::SetOk);
+        assert_eq!(registry.slot(0x11, 0).unwrap().value(), Value::I32(3));
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let mut registry = registry();
+        let set = Command::Set(0x12, Value::U32(1), DEV);
+        assert_eq!(
+            registry.dispatch(&set),
+            Response::Error(ErrorCode::TypeMismatch, 0x12)
+        );
+        assert_eq!(registry.slot(0x12, 0).unwrap().value(), Value::F32(0.0));
+        assert!(!registry.store(0x12, 0, Value::U32(1)));
+    }
+
+    #[test]
+    fn unknown_id_and_parameter() {
+        let mut registry = registry();
+        assert_eq!(
+            registry.dispatch(&Command::Get(0x13, 0, DEV)),
+            Response::Error(ErrorCode::UnknownId, 0x13)
+        );
+        assert_eq!(
+            registry.dispatch(&Command::Get(0x12, 2, DEV)),
+            Response::Error(ErrorCode::UnknownParameter, 2)
+        );
+        assert_eq!(
+            registry.dispatch(&Command::Set(0x13, Value::U32(1), DEV)),
+            Response::Error(ErrorCode::UnknownId, 0x13)
+        );
+        // declared but not as parameter 0
+        let registry =
+            ParameterRegistry::new([Slot::new(0x14, 1, Access::ReadWrite, Value::I32(0))]);
+        assert_eq!(
+            registry.check(0x14, &Value::I32(1)),
+            Err((ErrorCode::UnknownParameter, 0))
+        );
+    }
+
+    #[test]
+    fn hooks() {
+        let mut registry = registry();
+        let get = Command::Get(0x10, 0, DEV);
+        assert_eq!(
+            registry.dispatch(&get),
+            Response::Data(0x10, 0, Value::U32(1), DEV)
+        );
+        assert_eq!(
+            registry.dispatch(&get),
+            Response::Data(0x10, 0, Value::U32(2), DEV)
+        );
+        // not on the application side, nor when sampled
+        assert_eq!(registry.slot(0x10, 0).unwrap().value(), Value::U32(2));
+        assert_eq!(registry.sample(0x10, 0), Some(Value::U32(2)));
+        assert_eq!(registry.check_read(0x10, 0), Ok(()));
+        assert_eq!(registry.sample(0x10, 0), Some(Value::U32(2)));
+        assert_eq!(registry.sample(0x11, 0), None);
+        assert_eq!(
+            registry.check_read(0x11, 0),
+            Err((ErrorCode::WriteOnly, 0x11))
+        );
+
+        let set = Command::Set(0x12, Value::F32(2.0), DEV);
+        assert_eq!(
+            registry.dispatch(&set),
+            Response::Error(ErrorCode::OutOfRange, 0x12)
+        );
+        assert_eq!(registry.slot(0x12, 0).unwrap().value(), Value::F32(0.0));
+        let set = Command::Set(0x12, Value::F32(0.5), DEV);
+        assert_eq!(registry.dispatch(&set), Response::SetOk);
+        assert_eq!(registry.slot(0x12, 0).unwrap().value(), Value::F32(0.5));
+    }
+
+    #[test]
+    fn batch_failures_are_independent() {
+        let mut registry = registry();
+        let ops = [
+            Op::Set(0x12, Value::F32(0.25)),
+            Op::Get(0x11, 0),
+            Op::Get(0x10, 1),
+        ];
+        let batch = Command::Batch(ops.iter().copied().collect(), DEV);
+        let expected = [
+            Outcome::SetOk,
+            Outcome::Error(ErrorCode::WriteOnly, 0x11),
+            Outcome::Data(0x10, 1, Value::Bool(true)),
+        ];
+        assert_eq!(
+            registry.dispatch(&batch),
+            Response::Batch(expected.iter().copied().collect())
+        );
+    }
+}
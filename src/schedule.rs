@@ -0,0 +1,181 @@
+//! Servant side scheduled and staged `Set`s
+//!
+//! `Command::SetAt` queues a value to be written at a synchronised time (see `Clock`),
+//! `Command::Stage` queues a value until `Command::Trigger`. Broadcast by the master, the
+//! trigger latches the staged values of all servants at once. Queued values are applied
+//! by `poll`, called regularly (and right after a trigger) by the application.
+//!
+//! Values are checked when queued, a request that cannot be queued is answered by
+//! `ErrorCode::QueueFull`, one for a time already passed by `ErrorCode::TooLate`, and
+//! `Command::SetAt` before the clock is synchronised by `ErrorCode::InvalidState`.
+
+use crate::{Command, ErrorCode, Id, Micros, Response, Value};
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    id: Id,
+    value: Value,
+    /// When to apply the value, `None` when staged until the next trigger
+    at: Option<Micros>,
+    /// Arrival order, values due at the same time are applied in order
+    order: u32,
+}
+
+/// Up to `N` queued values
+pub struct Schedule<const N: usize> {
+    entries: [Option<Entry>; N],
+    order: u32,
+}
+
+impl<const N: usize> Default for Schedule<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Schedule<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; N],
+            order: 0,
+        }
+    }
+
+    /// Handle `Command::SetAt`, `Command::Stage` and `Command::Trigger` at the synchronised
+    /// time `now` (`None` while the clock is not synchronised, see `Clock::synced`),
+    /// `None` for other commands
+    ///
+    /// `check` validates a value as `Command::Set` would (e.g., `ParameterRegistry::check`).
+    /// Staging a value for an `Id` again replaces the staged value.
+    pub fn dispatch<F>(&mut self, cmd: &Command, now: Option<Micros>, check: F) -> Option<Response>
+    where
+        F: FnOnce(Id, &Value) -> Result<(), (ErrorCode, u32)>,
+    {
+        let (id, value, at) = match *cmd {
+            Command::SetAt { id, .. } if now.is_none() => {
+                return Some(Response::Error(ErrorCode::InvalidState, id));
+            }
+            Command::SetAt { id, value, at, .. } => (id, value, Some(at)),
+            Command::Stage(id, value, _) => (id, value, None),
+            Command::Trigger(_) => {
+                // without a synchronised time, the staged values are due right away
+                let now = now.unwrap_or(Micros::MIN);
+                for entry in self.entries.iter_mut().flatten() {
+                    entry.at = entry.at.or(Some(now));
+                }
+                return Some(Response::SetOk);
+            }
+            _ => return None,
+        };
+        if at.zip(now).is_some_and(|(at, now)| at < now) {
+            return Some(Response::Error(ErrorCode::TooLate, id));
+        }
+        if let Err((code, detail)) = check(id, &value) {
+            return Some(Response::Error(code, detail));
+        }
+        let staged = match at {
+            None => self
+                .entries
+                .iter()
+                .position(|e| matches!(e, Some(e) if e.id == id && e.at.is_none())),
+            Some(_) => None,
+        };
+        let Some(i) = staged.or_else(|| self.entries.iter().position(Option::is_none)) else {
+            return Some(Response::Error(ErrorCode::QueueFull, id));
+        };
+        self.entries[i] = Some(Entry {
+            id,
+            value,
+            at,
+            order: self.order,
+        });
+        self.order = self.order.wrapping_add(1);
+        Some(Response::SetOk)
+    }
+
+    /// Apply the values due at `now` by `apply`, in order of time, returns their number
+    pub fn poll<F>(&mut self, now: Micros, mut apply: F) -> usize
+    where
+        F: FnMut(Id, Value),
+    {
+        let mut applied = 0;
+        loop {
+            let due = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| match e {
+                    Some(Entry {
+                        at: Some(at),
+                        order,
+                        ..
+                    }) if *at <= now => Some((i, (*at, order.wrapping_sub(self.order)))),
+                    _ => None,
+                })
+                // earliest first, then oldest, whatever the wrapping of the arrival counter
+                .min_by_key(|(_, key)| *key);
+            let Some((i, _)) = due else {
+                return applied;
+            };
+            if let Some(entry) = self.entries[i].take() {
+                apply(entry.id, entry.value);
+                applied += 1;
+            }
+        }
+    }
+
+    /// Drop all queued values
+    pub fn clear(&mut self) {
+        self.entries = [None; N];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Clock;
+
+    fn set_at(at: Micros) -> Command {
+        Command::SetAt {
+            id: 0x12,
+            value: Value::U32(1),
+            at,
+            dev: 0b001,
+        }
+    }
+
+    #[test]
+    fn set_at_needs_synced_clock() {
+        let mut schedule: Schedule<2> = Schedule::new();
+        let mut clock = Clock::new();
+        let resp = schedule.dispatch(&set_at(1_000), clock.synced(500), |_, _| Ok(()));
+        assert_eq!(resp, Some(Response::Error(ErrorCode::InvalidState, 0x12)));
+        assert_eq!(schedule.poll(u64::MAX, |_, _| {}), 0);
+
+        let offset = Command::ClockOffset {
+            offset: 100,
+            dev: 0b001,
+        };
+        clock.dispatch(&offset, 0, 0);
+        let resp = schedule.dispatch(&set_at(1_000), clock.synced(500), |_, _| Ok(()));
+        assert_eq!(resp, Some(Response::SetOk));
+        assert_eq!(schedule.poll(999, |_, _| {}), 0);
+        assert_eq!(schedule.poll(1_000, |_, _| {}), 1);
+    }
+
+    #[test]
+    fn trigger_without_synced_clock() {
+        let mut schedule: Schedule<2> = Schedule::new();
+        let stage = Command::Stage(0x12, Value::U32(1), 0b001);
+        assert_eq!(
+            schedule.dispatch(&stage, None, |_, _| Ok(())),
+            Some(Response::SetOk)
+        );
+        let trigger = Command::Trigger(0b001);
+        assert_eq!(
+            schedule.dispatch(&trigger, None, |_, _| Ok(())),
+            Some(Response::SetOk)
+        );
+        assert_eq!(schedule.poll(0, |_, _| {}), 1);
+    }
+}
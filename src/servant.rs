@@ -0,0 +1,376 @@
+//! Servant side request handling
+//!
+//! The master retransmits a `Request` (with the same sequence number) when the
+//! `Reply` is lost. The `Servant` remembers the last `W` requests and their replies
+//! (its receive window), so a retransmission is answered again without executing the
+//! command twice. `W` should match the window size used by the master, stop-and-wait
+//! needs a single entry.
+//!
+//! Commands addressed to other servants are ignored, commands addressed to `BROADCAST`
+//! are executed without reply. Frames that could not be decoded (noise, or replies of
+//! other servants) are ignored as well, unless on a point to point link (see
+//! `with_error_replies`).
+//!
+//! Servants with a single bit `DevId` (e.g., `0b010`) also take part in multicast, a
+//! multicast request addressed to a mask (e.g., `0b110`) is executed by every servant
+//! whose bit is set. Other requests are addressed to the servant with exactly that
+//! `DevId`, so servants with multi bit `DevId`s (e.g., `0b011`) can share the bus.
+//! Replies are sent in time slots of `SLOT_BYTES`, ordered by bit position within the
+//! mask, so they do not collide.
+//!
+//! `Command::Ping` is answered by the `Servant` itself, reporting its `Info`, as are
+//! `Command::Heartbeat`, reporting its `Session`, and `Command::Hello`, reporting the
+//! protocol version, schema and capabilities of the build. Servants may also send
+//! heartbeats on their own (see `heartbeat`), e.g., on a point to point link.
+//!
+//! A servant created with `UNASSIGNED` (and its `Uid`) only takes part in address
+//! assignment: it answers `Command::Claim` while its `Uid` matches the prefix, and adopts
+//! the `DevId` of a `Command::Assign` carrying its `Uid`.
+
+use crate::{
+    Capabilities, Command, DevId, ErrorCode, FrameError, Hello, Info, Reply, Request, Response,
+    Session, Uid, Version, WireSize, BROADCAST, SEQ_UNKNOWN, UNASSIGNED,
+};
+
+/// Length of a multicast reply slot in byte times, a `Reply` frame plus guard time
+pub const SLOT_BYTES: usize = Reply::MAX_FRAME_SIZE + 4;
+
+/// A reply to send, after waiting `slot` multicast slots (of `SLOT_BYTES` each)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outgoing {
+    pub reply: Reply,
+    pub slot: u32,
+}
+
+/// Multicast slot of the servant `dev` addressed by `mask`, if any
+///
+/// Slots are assigned in the order of the set bits of `mask`.
+pub fn multicast_slot(mask: DevId, dev: DevId) -> Option<u32> {
+    (dev.is_power_of_two() && mask & dev != 0).then(|| (mask & (dev - 1)).count_ones())
+}
+
+pub struct Servant<const W: usize = 1> {
+    dev: DevId,
+    uid: Option<Uid>,
+    info: Info,
+    /// Capabilities declared by the application
+    features: Capabilities,
+    session: Session,
+    /// Answer frames that could not be decoded
+    error_replies: bool,
+    window: [Option<(Request, Reply)>; W],
+    next: usize,
+}
+
+impl<const W: usize> Servant<W> {
+    /// A servant with firmware version 0.0.0, see `with_version`
+    pub const fn new(dev: DevId) -> Self {
+        Self {
+            dev,
+            uid: None,
+            info: Info {
+                version: Version {
+                    major: 0,
+                    minor: 0,
+                    patch: 0,
+                },
+                capabilities: capabilities(dev),
+                window: if W > u8::MAX as usize {
+                    u8::MAX
+                } else {
+                    W as u8
+                },
+            },
+            features: Capabilities(0),
+            session: Session { boot: 0, id: 0 },
+            error_replies: false,
+            window: [const { None }; W],
+            next: 0,
+        }
+    }
+
+    /// Set the firmware version reported by `Response::Pong`
+    pub const fn with_version(mut self, version: Version) -> Self {
+        self.info.version = version;
+        self
+    }
+
+    /// Set the features served by the application (e.g., `Capabilities::BATCH`), reported
+    /// by `Response::Pong` and `Response::Hello`
+    pub const fn with_capabilities(mut self, features: Capabilities) -> Self {
+        self.features = features;
+        self.info.capabilities = capabilities(self.dev).union(features);
+        self
+    }
+
+    /// Set the unique hardware id, used for address assignment
+    pub const fn with_uid(mut self, uid: Uid) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Set the session reported by `Response::Heartbeat`, to be called at boot
+    pub const fn with_session(mut self, session: Session) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Answer frames that could not be decoded by `Response::Error` (`CrcError` or
+    /// `Malformed`, with the sequence number `SEQ_UNKNOWN`), and requests addressed to
+    /// another `DevId` by `NotAddressed`, so the master need not wait for its timeout
+    ///
+    /// Only for point to point links, on a bus every servant would answer any noise and
+    /// the replies it overhears from other servants.
+    pub const fn with_error_replies(mut self) -> Self {
+        self.error_replies = true;
+        self
+    }
+
+    /// Unique hardware id
+    pub fn uid(&self) -> Option<Uid> {
+        self.uid
+    }
+
+    /// Identification reported by `Response::Pong`
+    pub fn info(&self) -> Info {
+        self.info
+    }
+
+    /// Session reported by `Response::Heartbeat`
+    pub fn session(&self) -> Session {
+        self.session
+    }
+
+    /// Own device id
+    pub fn dev(&self) -> DevId {
+        self.dev
+    }
+
+    /// Handle a received frame, executing the command by `exec`, returns the reply to send (if any)
+    ///
+    /// `exec` is not called for `Command::Ping`, `Command::Heartbeat`, `Command::Hello`,
+    /// `Command::Claim` and `Command::Assign`.
+    ///
+    /// Frames that could not be decoded are ignored, unless `with_error_replies`.
+    pub fn handle<F>(&mut self, frame: Result<Request, FrameError>, exec: F) -> Option<Outgoing>
+    where
+        F: FnOnce(&Command) -> Response,
+    {
+        let request = match frame {
+            Ok(request) => request,
+            Err(_) if !self.error_replies => return None,
+            Err(err) => {
+                let resp = match err {
+                    FrameError::CrcMismatch { expected, .. } => {
+                        Response::Error(ErrorCode::CrcError, expected)
+                    }
+                    _ => Response::Error(ErrorCode::Malformed, 0),
+                };
+                return Some(Outgoing {
+                    reply: Reply {
+                        seq: SEQ_UNKNOWN,
+                        dev: self.dev,
+                        resp,
+                    },
+                    slot: 0,
+                });
+            }
+        };
+
+        match (&request.cmd, self.uid) {
+            (&Command::Claim(prefix, len, _), Some(uid))
+                if self.dev == UNASSIGNED && uid.has_prefix(prefix, len) =>
+            {
+                return Some(self.direct(&request, Response::Claim(uid)));
+            }
+            // addressed by `Uid` whatever the current `DevId`, so a retransmission is answered
+            (&Command::Assign(to, dev, _), Some(uid)) if to == uid => {
+                self.dev = dev;
+                self.info.capabilities = capabilities(dev).union(self.features);
+                return Some(self.direct(&request, Response::SetOk));
+            }
+            (Command::Claim(..) | Command::Assign(..), _) => return None,
+            _ => {}
+        }
+
+        let slot = match (request.cmd.dev(), request.multicast) {
+            (BROADCAST, _) => None,
+            // unassigned servants take no part until assigned
+            (UNASSIGNED, _) => return None,
+            (mask, true) => Some(multicast_slot(mask, self.dev)?),
+            (dev, false) if dev == self.dev => Some(0),
+            // the only servant on the link, tell the master it has the wrong `DevId`
+            (_, false) if self.error_replies => {
+                let resp = Response::Error(ErrorCode::NotAddressed, self.dev);
+                return Some(self.direct(&request, resp));
+            }
+            _ => return None,
+        };
+
+        let resp = match request.cmd {
+            Command::Ping(_) => Some(Response::Pong(self.info)),
+            Command::Heartbeat(_) => Some(Response::Heartbeat(self.session)),
+            Command::Hello(..) => Some(Response::Hello(Hello::new(
+                Request::MAX_FRAME_SIZE as u16,
+                self.info.capabilities,
+            ))),
+            _ => None,
+        };
+        if let Some(resp) = resp {
+            // side effect free, no need to go through the receive window
+            let reply = Reply {
+                seq: request.seq,
+                dev: self.dev,
+                resp,
+            };
+            return slot.map(|slot| Outgoing { reply, slot });
+        }
+
+        let reply = self.execute(request, exec);
+        slot.map(|slot| Outgoing { reply, slot })
+    }
+
+    /// A `Reply` not answering any request (e.g., `Response::Notify`), sent with the
+    /// sequence number `SEQ_UNKNOWN`
+    pub fn unsolicited(&self, resp: Response) -> Reply {
+        Reply {
+            seq: SEQ_UNKNOWN,
+            dev: self.dev,
+            resp,
+        }
+    }
+
+    /// An unsolicited `Response::Heartbeat`, for servant initiated keepalive
+    pub fn heartbeat(&self) -> Reply {
+        self.unsolicited(Response::Heartbeat(self.session))
+    }
+
+    /// Reply echoing the addressed `DevId`, e.g., to an assignment request
+    fn direct(&self, request: &Request, resp: Response) -> Outgoing {
+        Outgoing {
+            reply: Reply {
+                seq: request.seq,
+                dev: request.cmd.dev(),
+                resp,
+            },
+            slot: 0,
+        }
+    }
+
+    fn execute<F>(&mut self, request: Request, exec: F) -> Reply
+    where
+        F: FnOnce(&Command) -> Response,
+    {
+        for (last, reply) in self.window.iter().flatten() {
+            if *last == request {
+                // retransmission, the previous reply was lost
+                return reply.clone();
+            }
+        }
+
+        let reply = Reply {
+            seq: request.seq,
+            dev: self.dev,
+            resp: exec(&request.cmd),
+        };
+        if W > 0 {
+            self.window[self.next] = Some((request, reply.clone()));
+            self.next = (self.next + 1) % W;
+        }
+        reply
+    }
+}
+
+const fn capabilities(dev: DevId) -> Capabilities {
+    if dev.is_power_of_two() {
+        Capabilities::MULTICAST
+    } else {
+        Capabilities(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Seq, Value};
+
+    fn exec(_cmd: &Command) -> Response {
+        Response::SetOk
+    }
+
+    fn set(seq: Seq, value: u32) -> Request {
+        Request {
+            seq,
+            multicast: false,
+            cmd: Command::Set(7, Value::U32(value), 0b010),
+        }
+    }
+
+    /// Handle `request`, counting the commands executed
+    fn count<const W: usize>(servant: &mut Servant<W>, request: Request, executed: &mut usize) {
+        let reply = servant.handle(Ok(request), |_| {
+            *executed += 1;
+            Response::SetOk
+        });
+        assert_eq!(reply.map(|out| out.reply.resp), Some(Response::SetOk));
+    }
+
+    #[test]
+    fn retransmission_answered_from_window() {
+        let mut servant: Servant = Servant::new(0b010);
+        let mut executed = 0;
+        count(&mut servant, set(1, 5), &mut executed);
+        let again = servant.handle(Ok(set(1, 5)), |_| panic!("executed twice"));
+        assert_eq!(
+            again.unwrap().reply,
+            Reply {
+                seq: 1,
+                dev: 0b010,
+                resp: Response::SetOk,
+            }
+        );
+        // a new sequence number is a new request
+        count(&mut servant, set(2, 5), &mut executed);
+        assert_eq!(executed, 2);
+    }
+
+    #[test]
+    fn window_evicts_oldest() {
+        let mut servant: Servant<2> = Servant::new(0b010);
+        let mut executed = 0;
+        for seq in 1..=3 {
+            count(&mut servant, set(seq, 5), &mut executed);
+        }
+        // 2 and 3 in the window, 1 evicted
+        count(&mut servant, set(3, 5), &mut executed);
+        count(&mut servant, set(2, 5), &mut executed);
+        assert_eq!(executed, 3);
+        count(&mut servant, set(1, 5), &mut executed);
+        assert_eq!(executed, 4);
+    }
+
+    #[test]
+    fn undecodable_frames_are_ignored() {
+        let mut servant: Servant = Servant::new(0b010);
+        assert_eq!(servant.handle(Err(FrameError::CobsDecode), exec), None);
+        let crc = FrameError::CrcMismatch {
+            expected: 1,
+            actual: 2,
+        };
+        assert_eq!(servant.handle(Err(crc), exec), None);
+    }
+
+    #[test]
+    fn error_replies_on_point_to_point_links() {
+        let mut servant: Servant = Servant::new(0b010).with_error_replies();
+        let reply = servant.handle(Err(FrameError::Deserialize), exec).unwrap();
+        assert_eq!(
+            reply.reply,
+            Reply {
+                seq: SEQ_UNKNOWN,
+                dev: 0b010,
+                resp: Response::Error(ErrorCode::Malformed, 0),
+            }
+        );
+    }
+}
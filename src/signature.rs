@@ -0,0 +1,97 @@
+//! Signed firmware images
+//!
+//! A signed image is the firmware followed by a `TRAILER` of
+//! `SIGNATURE_MAGIC | major minor patch 0 | ed25519 signature`. The signature covers
+//! `signed_message`, i.e., the SHA-256 digest of the firmware and its version, so the
+//! servant checks it while hashing the staged image, without holding the image in RAM.
+//!
+//! `Verifier` holds the public key and the installed firmware version. Given to the
+//! `Updater`, images are only verified (and may only be committed) if they carry a valid
+//! signature and are not older than the installed firmware.
+
+use crate::{Digest, ErrorCode, Version};
+use ed25519_compact::Signature;
+
+/// Marks the trailer of a signed image
+pub const SIGNATURE_MAGIC: [u8; 4] = *b"MSIG";
+
+/// Bytes appended to a signed image
+pub const TRAILER: usize = 4 + 4 + 64;
+
+/// Bytes covered by the signature
+pub const SIGNED_MESSAGE: usize = 4 + 32 + 4;
+
+/// An ed25519 public key
+pub type PublicKey = [u8; 32];
+
+/// The message signed for firmware of `digest` and `version`
+pub fn signed_message(digest: &Digest, version: Version) -> [u8; SIGNED_MESSAGE] {
+    let mut message = [0; SIGNED_MESSAGE];
+    message[..4].copy_from_slice(&SIGNATURE_MAGIC);
+    message[4..36].copy_from_slice(digest);
+    message[36..].copy_from_slice(&version_bytes(version));
+    message
+}
+
+/// The trailer appended to firmware of `version`
+pub fn trailer(version: Version, signature: &[u8; 64]) -> [u8; TRAILER] {
+    let mut trailer = [0; TRAILER];
+    trailer[..4].copy_from_slice(&SIGNATURE_MAGIC);
+    trailer[4..8].copy_from_slice(&version_bytes(version));
+    trailer[8..].copy_from_slice(signature);
+    trailer
+}
+
+/// Checks the signature and version of images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verifier {
+    key: PublicKey,
+    installed: Version,
+}
+
+impl Verifier {
+    /// Accept images signed by `key`, of `installed` version or later
+    pub const fn new(key: PublicKey, installed: Version) -> Self {
+        Self { key, installed }
+    }
+
+    pub fn installed(&self) -> Version {
+        self.installed
+    }
+
+    /// Check `trailer` of an image whose firmware has `digest`, returning its version
+    ///
+    /// Fails with `ErrorCode::Unsigned` if there is no trailer, `ErrorCode::BadSignature`
+    /// if the firmware or version was altered (or signed by another key), and
+    /// `ErrorCode::Downgrade` if the version is older than the installed one.
+    pub fn check(
+        &self,
+        digest: &Digest,
+        trailer: &[u8; TRAILER],
+    ) -> Result<Version, (ErrorCode, u32)> {
+        if trailer[..4] != SIGNATURE_MAGIC || trailer[7] != 0 {
+            return Err((ErrorCode::Unsigned, 0));
+        }
+        let version = Version {
+            major: trailer[4],
+            minor: trailer[5],
+            patch: trailer[6],
+        };
+        let mut signature = [0; 64];
+        signature.copy_from_slice(&trailer[8..]);
+        ed25519_compact::PublicKey::new(self.key)
+            .verify(signed_message(digest, version), &Signature::new(signature))
+            .map_err(|_| (ErrorCode::BadSignature, 0))?;
+        if version < self.installed {
+            return Err((
+                ErrorCode::Downgrade,
+                u32::from_be_bytes(version_bytes(version)) >> 8,
+            ));
+        }
+        Ok(version)
+    }
+}
+
+fn version_bytes(version: Version) -> [u8; 4] {
+    [version.major, version.minor, version.patch, 0]
+}
@@ -0,0 +1,279 @@
+//! Servant side telemetry subscriptions
+//!
+//! `Command::Subscribe` asks the servant to push the value of a parameter by unsolicited
+//! `Response::Notify` frames, every `period_ms` (0 disables periodic notification) and
+//! whenever the value changed by more than `deadband` since the last notification
+//! (`f32::INFINITY` disables notification on change). Values are compared as `f32`, a
+//! value of another type always counts as changed. Notifications carry the time of
+//! sampling, in the master time base once the servant clock is synchronised (see `Clock`).
+//!
+//! `Subscriptions` keeps up to `N` subscriptions, `dispatch` handles `Subscribe` and
+//! `Unsubscribe`, `poll` (called regularly by the application) produces the notifications.
+//! Notifications are sent in a `Reply` with sequence number `SEQ_UNKNOWN`, see
+//! `Servant::unsolicited`. On a shared bus they may collide with replies of other
+//! servants, so subscriptions are best used on a point to point link or with
+//! sufficiently long periods.
+
+use crate::{Command, ErrorCode, Id, Micros, Parameter, Response, Value};
+
+#[derive(Debug, Clone, Copy)]
+struct Subscription {
+    id: Id,
+    parameter: Parameter,
+    period_ms: u32,
+    deadband: f32,
+    /// Time and value of the last notification
+    last: Option<(u32, Value)>,
+}
+
+impl Subscription {
+    fn due(&self, now_ms: u32, value: &Value) -> bool {
+        let Some((at, last)) = self.last else {
+            return true;
+        };
+        let periodic = self.period_ms != 0 && now_ms.wrapping_sub(at) >= self.period_ms;
+        periodic || changed(&last, value, self.deadband)
+    }
+}
+
+fn changed(last: &Value, value: &Value, deadband: f32) -> bool {
+    if core::mem::discriminant(last) != core::mem::discriminant(value) {
+        return true;
+    }
+    let delta = value.to_f32() - last.to_f32();
+    delta > deadband || -delta > deadband
+}
+
+/// Up to `N` telemetry subscriptions
+pub struct Subscriptions<const N: usize> {
+    slots: [Option<Subscription>; N],
+    /// Where `poll` starts looking, so every subscription gets its turn
+    next: usize,
+}
+
+impl<const N: usize> Default for Subscriptions<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Subscriptions<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Handle `Command::Subscribe`/`Command::Unsubscribe`, `None` for other commands
+    ///
+    /// `check` rejects parameters that cannot be subscribed to, as a `Command::Get` of
+    /// them would be (e.g., `ParameterRegistry::check_read`). Subscribing again to a
+    /// parameter replaces the subscription. `Busy` is returned when all `N` subscriptions
+    /// are taken.
+    pub fn dispatch<F>(&mut self, cmd: &Command, check: F) -> Option<Response>
+    where
+        F: FnOnce(Id, Parameter) -> Result<(), (ErrorCode, u32)>,
+    {
+        match *cmd {
+            Command::Subscribe {
+                id,
+                parameter,
+                period_ms,
+                deadband,
+                ..
+            } => {
+                if let Err((code, detail)) = check(id, parameter) {
+                    return Some(Response::Error(code, detail));
+                }
+                let subscription = Subscription {
+                    id,
+                    parameter,
+                    period_ms,
+                    deadband,
+                    last: None,
+                };
+                let slot = match self.find(id, parameter) {
+                    Some(i) => Some(i),
+                    None => self.slots.iter().position(Option::is_none),
+                };
+                Some(match slot {
+                    Some(i) => {
+                        self.slots[i] = Some(subscription);
+                        Response::SetOk
+                    }
+                    None => Response::Error(ErrorCode::Busy, id),
+                })
+            }
+            Command::Unsubscribe { id, parameter, .. } => {
+                if let Some(i) = self.find(id, parameter) {
+                    self.slots[i] = None;
+                }
+                Some(Response::SetOk)
+            }
+            _ => None,
+        }
+    }
+
+    /// Drop all subscriptions
+    pub fn clear(&mut self) {
+        self.slots = [None; N];
+    }
+
+    /// The next notification due at `now` (the servant time, e.g., `Clock::now`), if any
+    ///
+    /// `read` provides the current value of a parameter, subscriptions to parameters
+    /// that cannot be read are dropped. As it is called for every subscription on each
+    /// poll, `read` should sample a stored value rather than the hardware (e.g.,
+    /// `ParameterRegistry::sample`). At most one notification is produced per call.
+    pub fn poll<F>(&mut self, now: Micros, mut read: F) -> Option<Response>
+    where
+        F: FnMut(Id, Parameter) -> Option<Value>,
+    {
+        // periods are compared on a wrapping millisecond clock
+        let now_ms = (now / 1000) as u32;
+        for k in 0..N {
+            let i = (self.next + k) % N;
+            let Some(subscription) = &mut self.slots[i] else {
+                continue;
+            };
+            let Some(value) = read(subscription.id, subscription.parameter) else {
+                self.slots[i] = None;
+                continue;
+            };
+            if subscription.due(now_ms, &value) {
+                subscription.last = Some((now_ms, value));
+                self.next = (i + 1) % N;
+                return Some(Response::Notify(
+                    subscription.id,
+                    subscription.parameter,
+                    value,
+                    now,
+                ));
+            }
+        }
+        None
+    }
+
+    fn find(&self, id: Id, parameter: Parameter) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| matches!(s, Some(s) if s.id == id && s.parameter == parameter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV: u32 = 0b001;
+
+    fn subscribe(id: Id, period_ms: u32, deadband: f32) -> Command {
+        Command::Subscribe {
+            id,
+            parameter: 0,
+            period_ms,
+            deadband,
+            dev: DEV,
+        }
+    }
+
+    /// Parameters 0x10 to 0x1f are declared
+    fn check(id: Id, parameter: Parameter) -> Result<(), (ErrorCode, u32)> {
+        match (id, parameter) {
+            (0x10..=0x1f, 0) => Ok(()),
+            (0x10..=0x1f, _) => Err((ErrorCode::UnknownParameter, parameter)),
+            _ => Err((ErrorCode::UnknownId, id)),
+        }
+    }
+
+    #[test]
+    fn subscribe_checks_the_parameter() {
+        let mut subscriptions: Subscriptions<2> = Subscriptions::new();
+        assert_eq!(
+            subscriptions.dispatch(&subscribe(0x20, 100, 1.0), check),
+            Some(Response::Error(ErrorCode::UnknownId, 0x20))
+        );
+        let other = Command::Subscribe {
+            id: 0x10,
+            parameter: 1,
+            period_ms: 100,
+            deadband: 1.0,
+            dev: DEV,
+        };
+        assert_eq!(
+            subscriptions.dispatch(&other, check),
+            Some(Response::Error(ErrorCode::UnknownParameter, 1))
+        );
+        assert_eq!(subscriptions.poll(0, |_, _| Some(Value::U32(0))), None);
+        assert_eq!(subscriptions.dispatch(&Command::Ping(DEV), check), None);
+    }
+
+    #[test]
+    fn notify_on_change_and_period() {
+        let mut subscriptions: Subscriptions<2> = Subscriptions::new();
+        let resp = subscriptions.dispatch(&subscribe(0x10, 0, 1.0), check);
+        assert_eq!(resp, Some(Response::SetOk));
+        let mut value = Value::F32(20.0);
+        let notify = |v, at| Some(Response::Notify(0x10, 0, v, at));
+        // first poll always notifies
+        assert_eq!(subscriptions.poll(0, |_, _| Some(value)), notify(value, 0));
+        value = Value::F32(20.5);
+        assert_eq!(subscriptions.poll(1_000, |_, _| Some(value)), None);
+        // compared to the last notified value
+        value = Value::F32(21.5);
+        let poll = subscriptions.poll(2_000, |_, _| Some(value));
+        assert_eq!(poll, notify(value, 2_000));
+        // another type always counts as changed
+        value = Value::U32(21);
+        let poll = subscriptions.poll(3_000, |_, _| Some(value));
+        assert_eq!(poll, notify(value, 3_000));
+
+        // replaced by a periodic subscription
+        let resp = subscriptions.dispatch(&subscribe(0x10, 10, f32::INFINITY), check);
+        assert_eq!(resp, Some(Response::SetOk));
+        let poll = subscriptions.poll(4_000, |_, _| Some(value));
+        assert_eq!(poll, notify(value, 4_000));
+        assert_eq!(subscriptions.poll(13_000, |_, _| Some(value)), None);
+        let poll = subscriptions.poll(14_000, |_, _| Some(value));
+        assert_eq!(poll, notify(value, 14_000));
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let mut subscriptions: Subscriptions<2> = Subscriptions::new();
+        subscriptions.dispatch(&subscribe(0x10, 10, 0.0), check);
+        let unsubscribe = Command::Unsubscribe {
+            id: 0x10,
+            parameter: 0,
+            dev: DEV,
+        };
+        let resp = subscriptions.dispatch(&unsubscribe, check);
+        assert_eq!(resp, Some(Response::SetOk));
+        assert_eq!(subscriptions.poll(0, |_, _| Some(Value::U32(0))), None);
+        // not subscribed
+        let resp = subscriptions.dispatch(&unsubscribe, check);
+        assert_eq!(resp, Some(Response::SetOk));
+    }
+
+    #[test]
+    fn capacity_full() {
+        let mut subscriptions: Subscriptions<2> = Subscriptions::new();
+        for id in [0x10, 0x11] {
+            let resp = subscriptions.dispatch(&subscribe(id, 10, 0.0), check);
+            assert_eq!(resp, Some(Response::SetOk));
+        }
+        assert_eq!(
+            subscriptions.dispatch(&subscribe(0x12, 10, 0.0), check),
+            Some(Response::Error(ErrorCode::Busy, 0x12))
+        );
+        // replacing an existing subscription needs no free slot
+        let resp = subscriptions.dispatch(&subscribe(0x11, 20, 0.0), check);
+        assert_eq!(resp, Some(Response::SetOk));
+        // a parameter no longer readable frees its slot
+        let read = |id, _| (id == 0x11).then_some(Value::U32(0));
+        assert!(subscriptions.poll(0, read).is_some());
+        let resp = subscriptions.dispatch(&subscribe(0x12, 10, 0.0), check);
+        assert_eq!(resp, Some(Response::SetOk));
+    }
+}
@@ -0,0 +1,123 @@
+//! Servant side time synchronisation
+//!
+//! The master measures the offset of a servant clock by an NTP-style exchange: it sends
+//! `Command::TimeSync` at `t1` (master clock), the servant receives it at `t2` and replies
+//! at `t3` (servant clock), the master receives the reply at `t4`. Assuming symmetric
+//! transmission delays the servant clock is ahead by `((t2 - t1) + (t3 - t4)) / 2`, the
+//! round trip delay is `(t4 - t1) - (t3 - t2)`. The master tells the servant the offset by
+//! `Command::ClockOffset`.
+//!
+//! `Clock` answers both commands and converts the servant's own (monotonic) time to the
+//! master time base, for timestamping telemetry and scheduling actions. Times are in
+//! microseconds (`Micros`), the servant clock may start at any value.
+
+use crate::{Command, Micros, Response};
+
+/// Offset of the servant clock to the master clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Clock {
+    /// Servant time minus master time, `None` until synchronised
+    offset: Option<i64>,
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Self { offset: None }
+    }
+
+    /// Handle `Command::TimeSync`/`Command::ClockOffset`, `None` for other commands
+    ///
+    /// `received` is the local time the request frame was received at, `now` the local
+    /// time the reply is sent at (as closely as known).
+    pub fn dispatch(&mut self, cmd: &Command, received: Micros, now: Micros) -> Option<Response> {
+        match *cmd {
+            Command::TimeSync { t1, .. } => Some(Response::TimeSync {
+                t1,
+                t2: received,
+                t3: now,
+            }),
+            Command::ClockOffset { offset, .. } => {
+                self.offset = Some(offset);
+                Some(Response::SetOk)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the offset to the master clock is known
+    pub fn is_synced(&self) -> bool {
+        self.offset.is_some()
+    }
+
+    /// Local time `local` in the master time base, the local time while not synchronised
+    pub fn now(&self, local: Micros) -> Micros {
+        local.wrapping_add_signed(-self.offset.unwrap_or(0))
+    }
+
+    /// Local time `local` in the master time base, `None` while not synchronised
+    pub fn synced(&self, local: Micros) -> Option<Micros> {
+        self.is_synced().then(|| self.now(local))
+    }
+
+    /// Master time `at` in the local time base, the inverse of `now`
+    pub fn local(&self, at: Micros) -> Micros {
+        at.wrapping_add_signed(self.offset.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEV: u32 = 0b001;
+
+    #[test]
+    fn local_time_until_synced() {
+        let clock = Clock::new();
+        assert!(!clock.is_synced());
+        assert_eq!(clock.now(1_234), 1_234);
+        assert_eq!(clock.local(1_234), 1_234);
+        assert_eq!(clock.synced(1_234), None);
+    }
+
+    #[test]
+    fn time_sync_reports_timestamps() {
+        let mut clock = Clock::new();
+        let sync = Command::TimeSync { t1: 42, dev: DEV };
+        let resp = clock.dispatch(&sync, 1_000, 1_100);
+        assert_eq!(
+            resp,
+            Some(Response::TimeSync {
+                t1: 42,
+                t2: 1_000,
+                t3: 1_100
+            })
+        );
+        // not synced by the exchange alone
+        assert!(!clock.is_synced());
+        assert_eq!(clock.dispatch(&Command::Ping(DEV), 0, 0), None);
+    }
+
+    #[test]
+    fn offset_converts_between_time_bases() {
+        let mut clock = Clock::new();
+        let offset = Command::ClockOffset {
+            offset: 5_000_000,
+            dev: DEV,
+        };
+        assert_eq!(clock.dispatch(&offset, 0, 0), Some(Response::SetOk));
+        assert!(clock.is_synced());
+        assert_eq!(clock.now(5_001_000), 1_000);
+        assert_eq!(clock.synced(5_001_000), Some(1_000));
+        assert_eq!(clock.local(1_000), 5_001_000);
+
+        // servant behind the master
+        let offset = Command::ClockOffset {
+            offset: -2_000,
+            dev: DEV,
+        };
+        clock.dispatch(&offset, 0, 0);
+        assert_eq!(clock.now(1_000), 3_000);
+        assert_eq!(clock.local(3_000), 1_000);
+    }
+}
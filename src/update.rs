@@ -0,0 +1,642 @@
+//! Servant side firmware update
+//!
+//! The master announces the image by `Command::UpdateBegin` (size and SHA-256 digest),
+//! sends it in numbered `Command::UpdateChunk`s of `CHUNK` bytes (each with its own crc),
+//! asks the servant to check the digest of the received image (`Command::UpdateVerify`)
+//! and finally to adopt it (`Command::UpdateCommit`), or gives up (`Command::UpdateAbort`).
+//!
+//! `Updater` implements the servant side, writing the image into a `Staging` area (e.g.,
+//! the second flash bank, or `RamStaging` in tests). Chunks are accepted in order,
+//! retransmitted chunks are acknowledged again. `UpdateBegin` for the image being received
+//! does not start over, it is answered by the number of bytes received so far, so an
+//! upload interrupted by link loss is resumed. Likewise verifying and committing the
+//! image again succeed, so the master can repeat the whole sequence. A committed update
+//! can no longer be aborted.
+//!
+//! With a `Verifier` (`Updater::with_verifier`) the image must also be signed, see
+//! `signature`; unsigned, altered and older images fail to verify and cannot be committed.
+
+use crate::{Command, ErrorCode, Response, Verifier, CKSUM, TRAILER};
+use sha2::{Digest as _, Sha256};
+
+/// Image bytes carried by a `Command::UpdateChunk`
+pub const CHUNK: usize = 32;
+
+/// SHA-256 digest of an image
+pub type Digest = [u8; 32];
+
+/// SHA-256 digest of `image`, as carried by `Command::UpdateBegin`
+pub fn digest(image: &[u8]) -> Digest {
+    Sha256::digest(image).into()
+}
+
+/// Storage for the received image
+///
+/// Failures are reported to the master (e.g., `ErrorCode::Storage`).
+pub trait Staging {
+    /// Largest image that can be staged
+    fn capacity(&self) -> u32;
+
+    /// Prepare for an image of `size` bytes, discarding any staged image
+    fn erase(&mut self, size: u32) -> Result<(), ErrorCode>;
+
+    /// Write `data` at `offset`, chunks are written in order
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Read back `buf.len()` bytes at `offset`
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode>;
+
+    /// Mark the verified image of `size` bytes to be booted
+    fn commit(&mut self, size: u32) -> Result<(), ErrorCode>;
+}
+
+impl<S: Staging + ?Sized> Staging for &mut S {
+    fn capacity(&self) -> u32 {
+        (**self).capacity()
+    }
+
+    fn erase(&mut self, size: u32) -> Result<(), ErrorCode> {
+        (**self).erase(size)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+        (**self).write(offset, data)
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        (**self).read(offset, buf)
+    }
+
+    fn commit(&mut self, size: u32) -> Result<(), ErrorCode> {
+        (**self).commit(size)
+    }
+}
+
+/// A staging area of `N` bytes of RAM
+pub struct RamStaging<const N: usize> {
+    buf: [u8; N],
+    committed: Option<u32>,
+}
+
+impl<const N: usize> Default for RamStaging<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RamStaging<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            committed: None,
+        }
+    }
+
+    /// The committed image, if any
+    pub fn committed(&self) -> Option<&[u8]> {
+        self.committed.map(|size| &self.buf[..size as usize])
+    }
+}
+
+impl<const N: usize> Staging for RamStaging<N> {
+    fn capacity(&self) -> u32 {
+        N as u32
+    }
+
+    fn erase(&mut self, size: u32) -> Result<(), ErrorCode> {
+        let area = self
+            .buf
+            .get_mut(..size as usize)
+            .ok_or(ErrorCode::Storage)?;
+        // as erased flash
+        area.fill(0xff);
+        self.committed = None;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ErrorCode> {
+        let start = offset as usize;
+        self.buf
+            .get_mut(start..start + data.len())
+            .ok_or(ErrorCode::Storage)?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let start = offset as usize;
+        buf.copy_from_slice(
+            self.buf
+                .get(start..start + buf.len())
+                .ok_or(ErrorCode::Storage)?,
+        );
+        Ok(())
+    }
+
+    fn commit(&mut self, size: u32) -> Result<(), ErrorCode> {
+        self.committed = Some(size);
+        Ok(())
+    }
+}
+
+/// Progress of an update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateState {
+    Idle,
+    /// `received` of `size` bytes staged
+    Receiving {
+        size: u32,
+        digest: Digest,
+        received: u32,
+    },
+    /// The staged image matches its digest (and signature)
+    Verified {
+        size: u32,
+        digest: Digest,
+    },
+    /// The image is to be booted, the application should reset
+    Committed {
+        size: u32,
+        digest: Digest,
+    },
+}
+
+/// The firmware update state machine
+pub struct Updater<S> {
+    staging: S,
+    state: UpdateState,
+    verifier: Option<Verifier>,
+}
+
+impl<S: Staging> Updater<S> {
+    pub const fn new(staging: S) -> Self {
+        Self {
+            staging,
+            state: UpdateState::Idle,
+            verifier: None,
+        }
+    }
+
+    /// Only accept images signed for `verifier`
+    pub const fn with_verifier(mut self, verifier: Verifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    pub fn state(&self) -> UpdateState {
+        self.state
+    }
+
+    pub fn staging(&self) -> &S {
+        &self.staging
+    }
+
+    pub fn staging_mut(&mut self) -> &mut S {
+        &mut self.staging
+    }
+
+    /// Handle the update commands, `None` for other commands
+    pub fn dispatch(&mut self, cmd: &Command) -> Option<Response> {
+        let result = match *cmd {
+            Command::UpdateBegin { size, digest, .. } => self.begin(size, digest),
+            Command::UpdateChunk {
+                index,
+                ref data,
+                crc,
+                ..
+            } => self.chunk(index, data, crc),
+            Command::UpdateVerify(_) => self.verify(),
+            Command::UpdateCommit(_) => self.commit(),
+            Command::UpdateAbort(_) => self.abort(),
+            _ => return None,
+        };
+        Some(result.unwrap_or_else(|(code, detail)| Response::Error(code, detail)))
+    }
+
+    fn begin(&mut self, size: u32, digest: Digest) -> Result<Response, (ErrorCode, u32)> {
+        let received = match self.state {
+            UpdateState::Receiving {
+                size: s,
+                digest: d,
+                received,
+            } if (s, d) == (size, digest) => received,
+            UpdateState::Verified { size: s, digest: d }
+            | UpdateState::Committed { size: s, digest: d }
+                if (s, d) == (size, digest) =>
+            {
+                size
+            }
+            _ => {
+                if size > self.staging.capacity() {
+                    return Err((ErrorCode::OutOfRange, size));
+                }
+                self.state = UpdateState::Idle;
+                self.staging.erase(size).map_err(|code| (code, 0))?;
+                self.state = UpdateState::Receiving {
+                    size,
+                    digest,
+                    received: 0,
+                };
+                0
+            }
+        };
+        Ok(Response::UpdateReady { received })
+    }
+
+    fn chunk(&mut self, index: u32, data: &[u8], crc: u32) -> Result<Response, (ErrorCode, u32)> {
+        let UpdateState::Receiving {
+            size,
+            ref mut received,
+            ..
+        } = self.state
+        else {
+            return Err((ErrorCode::InvalidState, index));
+        };
+        if CKSUM.checksum(data) != crc {
+            return Err((ErrorCode::CrcError, index));
+        }
+        let offset = index as u64 * CHUNK as u64;
+        let expected = (CHUNK as u64).min((size as u64).saturating_sub(offset));
+        if data.len() as u64 != expected || expected == 0 {
+            return Err((ErrorCode::OutOfRange, index));
+        }
+        let offset = offset as u32;
+        if offset < *received {
+            // retransmission, already written
+            return Ok(Response::SetOk);
+        }
+        if offset > *received {
+            return Err((ErrorCode::OutOfOrder, *received / CHUNK as u32));
+        }
+        self.staging
+            .write(offset, data)
+            .map_err(|code| (code, index))?;
+        *received += data.len() as u32;
+        Ok(Response::SetOk)
+    }
+
+    fn verify(&mut self) -> Result<Response, (ErrorCode, u32)> {
+        let (size, expected) = match self.state {
+            UpdateState::Receiving {
+                size,
+                digest,
+                received,
+            } if received == size => (size, digest),
+            UpdateState::Receiving { received, .. } => {
+                return Err((ErrorCode::OutOfOrder, received / CHUNK as u32))
+            }
+            UpdateState::Verified { .. } | UpdateState::Committed { .. } => {
+                return Ok(Response::SetOk)
+            }
+            _ => return Err((ErrorCode::InvalidState, 0)),
+        };
+        // the firmware, followed by the trailer of a signed image
+        let firmware = size.saturating_sub(TRAILER as u32);
+        let mut hasher = Sha256::new();
+        let mut signed = Sha256::new();
+        let mut trailer = [0; TRAILER];
+        let mut buf = [0; CHUNK];
+        let mut offset = 0;
+        while offset < size {
+            let n = (size - offset).min(CHUNK as u32);
+            let buf = &mut buf[..n as usize];
+            self.staging
+                .read(offset, buf)
+                .map_err(|code| (code, offset))?;
+            hasher.update(&*buf);
+            let split = (firmware.saturating_sub(offset) as usize).min(buf.len());
+            signed.update(&buf[..split]);
+            if split < buf.len() {
+                let at = offset as usize + split - firmware as usize;
+                trailer[at..at + buf.len() - split].copy_from_slice(&buf[split..]);
+            }
+            offset += n;
+        }
+        let mut result = Ok(());
+        if <Digest>::from(hasher.finalize()) != expected {
+            result = Err((ErrorCode::VerifyFailed, 0));
+        } else if let Some(verifier) = self.verifier {
+            result = if size < TRAILER as u32 {
+                Err((ErrorCode::Unsigned, 0))
+            } else {
+                verifier
+                    .check(&signed.finalize().into(), &trailer)
+                    .map(|_| ())
+            };
+        }
+        if let Err(err) = result {
+            // start over
+            self.state = UpdateState::Idle;
+            return Err(err);
+        }
+        self.state = UpdateState::Verified {
+            size,
+            digest: expected,
+        };
+        Ok(Response::SetOk)
+    }
+
+    fn commit(&mut self) -> Result<Response, (ErrorCode, u32)> {
+        match self.state {
+            UpdateState::Verified { size, digest } => {
+                self.staging.commit(size).map_err(|code| (code, 0))?;
+                self.state = UpdateState::Committed { size, digest };
+                Ok(Response::SetOk)
+            }
+            UpdateState::Committed { .. } => Ok(Response::SetOk),
+            _ => Err((ErrorCode::InvalidState, 0)),
+        }
+    }
+
+    /// Discard the update, unless committed: the image is marked to be booted already
+    fn abort(&mut self) -> Result<Response, (ErrorCode, u32)> {
+        if let UpdateState::Committed { .. } = self.state {
+            return Err((ErrorCode::InvalidState, 0));
+        }
+        self.state = UpdateState::Idle;
+        Ok(Response::SetOk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{signature, DevId, Version};
+    use ed25519_compact::{KeyPair, Seed};
+
+    const DEV: DevId = 0b001;
+    const V1: Version = Version {
+        major: 0,
+        minor: 1,
+        patch: 0,
+    };
+
+    type Up = Updater<RamStaging<256>>;
+
+    // 3 full chunks and a short one
+    fn image() -> [u8; 100] {
+        core::array::from_fn(|i| i as u8)
+    }
+
+    fn begin(image: &[u8]) -> Command {
+        Command::UpdateBegin {
+            size: image.len() as u32,
+            digest: digest(image),
+            dev: DEV,
+        }
+    }
+
+    fn chunk(image: &[u8], index: u32) -> Command {
+        let data = image.chunks(CHUNK).nth(index as usize).unwrap();
+        Command::UpdateChunk {
+            index,
+            data: heapless::Vec::from_slice(data).unwrap(),
+            crc: CKSUM.checksum(data),
+            dev: DEV,
+        }
+    }
+
+    fn upload(updater: &mut Up, image: &[u8]) {
+        updater.dispatch(&begin(image));
+        for index in 0..image.len().div_ceil(CHUNK) as u32 {
+            assert_eq!(
+                updater.dispatch(&chunk(image, index)),
+                Some(Response::SetOk)
+            );
+        }
+    }
+
+    fn key(seed: u8) -> KeyPair {
+        KeyPair::from_seed(Seed::new([seed; 32]))
+    }
+
+    fn signed(firmware: &[u8], key: &KeyPair, version: Version) -> heapless::Vec<u8, 256> {
+        let message = signature::signed_message(&digest(firmware), version);
+        let signature = key.sk.sign(message, None);
+        let mut image = heapless::Vec::from_slice(firmware).unwrap();
+        image
+            .extend_from_slice(&signature::trailer(version, &signature))
+            .unwrap();
+        image
+    }
+
+    fn verifier(key: &KeyPair) -> Verifier {
+        Verifier::new(*key.pk, V1)
+    }
+
+    #[test]
+    fn begin_resumes_at_received_offset() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        let ready = |received| Some(Response::UpdateReady { received });
+        assert_eq!(updater.dispatch(&begin(&image)), ready(0));
+        updater.dispatch(&chunk(&image, 0));
+        updater.dispatch(&chunk(&image, 1));
+        assert_eq!(updater.dispatch(&begin(&image)), ready(64));
+        assert_eq!(updater.dispatch(&chunk(&image, 2)), Some(Response::SetOk));
+
+        // another image starts over
+        assert_eq!(updater.dispatch(&begin(&image[..50])), ready(0));
+        assert_eq!(
+            updater.dispatch(&chunk(&image[..50], 1)),
+            Some(Response::Error(ErrorCode::OutOfOrder, 0))
+        );
+    }
+
+    #[test]
+    fn out_of_order_and_duplicate_chunks() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        updater.dispatch(&begin(&image));
+        assert_eq!(
+            updater.dispatch(&chunk(&image, 1)),
+            Some(Response::Error(ErrorCode::OutOfOrder, 0))
+        );
+        updater.dispatch(&chunk(&image, 0));
+        assert_eq!(
+            updater.dispatch(&chunk(&image, 2)),
+            Some(Response::Error(ErrorCode::OutOfOrder, 1))
+        );
+        // retransmissions are acknowledged, not written again
+        assert_eq!(updater.dispatch(&chunk(&image, 0)), Some(Response::SetOk));
+        assert_eq!(updater.dispatch(&chunk(&image, 0)), Some(Response::SetOk));
+        assert!(matches!(
+            updater.state(),
+            UpdateState::Receiving { received: 32, .. }
+        ));
+        for index in 1..4 {
+            assert_eq!(
+                updater.dispatch(&chunk(&image, index)),
+                Some(Response::SetOk)
+            );
+        }
+        assert_eq!(
+            updater.dispatch(&Command::UpdateVerify(DEV)),
+            Some(Response::SetOk)
+        );
+    }
+
+    #[test]
+    fn bad_chunk_crc() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        updater.dispatch(&begin(&image));
+        let Command::UpdateChunk { data, crc, .. } = chunk(&image, 0) else {
+            unreachable!()
+        };
+        let corrupt = Command::UpdateChunk {
+            index: 0,
+            data,
+            crc: crc ^ 1,
+            dev: DEV,
+        };
+        assert_eq!(
+            updater.dispatch(&corrupt),
+            Some(Response::Error(ErrorCode::CrcError, 0))
+        );
+        assert!(matches!(
+            updater.state(),
+            UpdateState::Receiving { received: 0, .. }
+        ));
+        assert_eq!(updater.dispatch(&chunk(&image, 0)), Some(Response::SetOk));
+    }
+
+    #[test]
+    fn digest_mismatch() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        updater.dispatch(&Command::UpdateBegin {
+            size: image.len() as u32,
+            digest: digest(&image[1..]),
+            dev: DEV,
+        });
+        for index in 0..4 {
+            updater.dispatch(&chunk(&image, index));
+        }
+        assert_eq!(
+            updater.dispatch(&Command::UpdateVerify(DEV)),
+            Some(Response::Error(ErrorCode::VerifyFailed, 0))
+        );
+        assert_eq!(updater.state(), UpdateState::Idle);
+        assert_eq!(
+            updater.dispatch(&Command::UpdateCommit(DEV)),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+        assert_eq!(updater.staging().committed(), None);
+    }
+
+    #[test]
+    fn commit() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        upload(&mut updater, &image);
+        assert_eq!(
+            updater.dispatch(&Command::UpdateCommit(DEV)),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+        for cmd in [Command::UpdateVerify(DEV), Command::UpdateCommit(DEV)] {
+            assert_eq!(updater.dispatch(&cmd), Some(Response::SetOk));
+        }
+        assert_eq!(updater.staging().committed(), Some(&image[..]));
+        assert!(matches!(updater.state(), UpdateState::Committed { .. }));
+
+        // the master may repeat the whole sequence
+        let size = image.len() as u32;
+        assert_eq!(
+            updater.dispatch(&begin(&image)),
+            Some(Response::UpdateReady { received: size })
+        );
+        for cmd in [Command::UpdateVerify(DEV), Command::UpdateCommit(DEV)] {
+            assert_eq!(updater.dispatch(&cmd), Some(Response::SetOk));
+        }
+        assert_eq!(updater.staging().committed(), Some(&image[..]));
+    }
+
+    #[test]
+    fn abort() {
+        let image = image();
+        let mut updater = Up::new(RamStaging::new());
+        upload(&mut updater, &image);
+        let abort = Command::UpdateAbort(DEV);
+        assert_eq!(updater.dispatch(&abort), Some(Response::SetOk));
+        assert_eq!(updater.state(), UpdateState::Idle);
+        assert_eq!(
+            updater.dispatch(&begin(&image)),
+            Some(Response::UpdateReady { received: 0 })
+        );
+
+        upload(&mut updater, &image);
+        for cmd in [Command::UpdateVerify(DEV), Command::UpdateCommit(DEV)] {
+            assert_eq!(updater.dispatch(&cmd), Some(Response::SetOk));
+        }
+        // too late
+        assert_eq!(
+            updater.dispatch(&abort),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+        assert!(matches!(updater.state(), UpdateState::Committed { .. }));
+        assert_eq!(updater.staging().committed(), Some(&image[..]));
+    }
+
+    #[test]
+    fn signature_checks() {
+        let firmware = image();
+        let signer = key(1);
+        let verify = |image: &[u8], verifier| {
+            let mut updater = Up::new(RamStaging::new()).with_verifier(verifier);
+            upload(&mut updater, image);
+            updater.dispatch(&Command::UpdateVerify(DEV))
+        };
+        let error = |code| Some(Response::Error(code, 0));
+
+        assert_eq!(
+            verify(&firmware, verifier(&signer)),
+            error(ErrorCode::Unsigned)
+        );
+        assert_eq!(
+            verify(&signed(&firmware, &key(2), V1), verifier(&signer)),
+            error(ErrorCode::BadSignature)
+        );
+        let mut altered = signed(&firmware, &signer, V1);
+        altered[0] ^= 1;
+        assert_eq!(
+            verify(&altered, verifier(&signer)),
+            error(ErrorCode::BadSignature)
+        );
+        let old = Version { minor: 0, ..V1 };
+        assert_eq!(
+            verify(&signed(&firmware, &signer, old), verifier(&signer)),
+            error(ErrorCode::Downgrade)
+        );
+        assert_eq!(
+            verify(&signed(&firmware, &signer, V1), verifier(&signer)),
+            Some(Response::SetOk)
+        );
+    }
+
+    #[test]
+    fn signed_commit() {
+        let firmware = image();
+        let signer = key(1);
+        let image = signed(&firmware, &signer, V1);
+        let mut updater = Up::new(RamStaging::new()).with_verifier(verifier(&signer));
+        upload(&mut updater, &image);
+        for cmd in [Command::UpdateVerify(DEV), Command::UpdateCommit(DEV)] {
+            assert_eq!(updater.dispatch(&cmd), Some(Response::SetOk));
+        }
+        assert_eq!(updater.staging().committed(), Some(&image[..]));
+
+        // an unsigned image fails to verify and cannot be committed
+        let mut updater = Up::new(RamStaging::new()).with_verifier(verifier(&signer));
+        upload(&mut updater, &firmware);
+        assert_eq!(
+            updater.dispatch(&Command::UpdateVerify(DEV)),
+            Some(Response::Error(ErrorCode::Unsigned, 0))
+        );
+        assert_eq!(
+            updater.dispatch(&Command::UpdateCommit(DEV)),
+            Some(Response::Error(ErrorCode::InvalidState, 0))
+        );
+        assert_eq!(updater.staging().committed(), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Typed parameter values
+//!
+//! `Value` is carried by `Command::Set` and `Response::Data` alike, so a value read back
+//! has the type it was written with. Conversions from and to the plain types are
+//! provided by `From`/`TryFrom`, the latter failing (returning the value) on a type mismatch.
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::WireSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, WireSize)]
+#[repr(C)]
+pub enum Value {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    F32(f32), // we might consider "f16" but not sure it plays well with `ssmarshal`
+    Fixed(Fixed),
+}
+
+impl Value {
+    /// The value as `f32`, `true` as 1.0, may round large integers
+    pub fn to_f32(&self) -> f32 {
+        match *self {
+            Value::Bool(b) => b as u8 as f32,
+            Value::I32(v) => v as f32,
+            Value::U32(v) => v as f32,
+            Value::F32(f) => f,
+            Value::Fixed(f) => f.to_f32(),
+        }
+    }
+}
+
+/// Fixed-point number, `raw / 2^frac_bits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, WireSize)]
+pub struct Fixed {
+    pub raw: i32,
+    pub frac_bits: u8,
+}
+
+impl Fixed {
+    pub const fn new(raw: i32, frac_bits: u8) -> Self {
+        Self { raw, frac_bits }
+    }
+
+    /// The nearest fixed-point number, saturating at the bounds of the `i32` raw value
+    pub fn from_f32(f: f32, frac_bits: u8) -> Self {
+        // `as` saturates and maps NaN to 0
+        let raw = (f * scale(frac_bits) + if f < 0.0 { -0.5 } else { 0.5 }) as i32;
+        Self { raw, frac_bits }
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.raw as f32 / scale(self.frac_bits)
+    }
+}
+
+fn scale(frac_bits: u8) -> f32 {
+    (1u64 << frac_bits.min(63)) as f32
+}
+
+macro_rules! value_conversions {
+    ($($t:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$t> for Value {
+                fn from(v: $t) -> Self {
+                    Value::$variant(v)
+                }
+            }
+
+            impl TryFrom<Value> for $t {
+                type Error = Value;
+
+                fn try_from(value: Value) -> Result<Self, Value> {
+                    match value {
+                        Value::$variant(v) => Ok(v),
+                        other => Err(other),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+value_conversions! {
+    bool => Bool,
+    i32 => I32,
+    u32 => U32,
+    f32 => F32,
+    Fixed => Fixed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deserialize_crc_cobs, serialize_crc_cobs, Command, Frame};
+
+    fn values() -> [Value; 5] {
+        [
+            Value::Bool(true),
+            Value::I32(-123_456),
+            Value::U32(u32::MAX),
+            Value::F32(-0.375),
+            Value::Fixed(Fixed::new(-3 << 12, 12)),
+        ]
+    }
+
+    #[test]
+    fn round_trip_on_the_wire() {
+        for value in values() {
+            let set = Command::Set(0x12, value, 0b001);
+            let mut buf = Command::frame_buf();
+            let n = serialize_crc_cobs(&set, &mut buf).unwrap().len();
+            assert_eq!(deserialize_crc_cobs::<Command>(&mut buf[..n]), Ok(set));
+        }
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(bool::try_from(Value::from(true)), Ok(true));
+        assert_eq!(i32::try_from(Value::from(-7i32)), Ok(-7));
+        assert_eq!(u32::try_from(Value::from(7u32)), Ok(7));
+        assert_eq!(f32::try_from(Value::from(0.5f32)), Ok(0.5));
+        let fixed = Fixed::from_f32(-3.0, 12);
+        assert_eq!(Fixed::try_from(Value::from(fixed)), Ok(fixed));
+        assert_eq!(fixed.to_f32(), -3.0);
+    }
+
+    #[test]
+    fn mismatch_returns_the_value() {
+        assert_eq!(u32::try_from(Value::I32(-1)), Err(Value::I32(-1)));
+        assert_eq!(f32::try_from(Value::U32(1)), Err(Value::U32(1)));
+        assert_eq!(bool::try_from(Value::U32(1)), Err(Value::U32(1)));
+    }
+}
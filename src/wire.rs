@@ -0,0 +1,138 @@
+//! Statically computed wire and frame sizes
+//!
+//! Unlike `size_of`, the wire size reflects the `ssmarshal` encoding (no padding,
+//! a single byte enum discriminant). Use `#[derive(WireSize)]` for your own types.
+//!
+//! `usize`/`isize` deliberately lack an implementation, their width differs between
+//! the 64-bit master and the 32-bit servant.
+//!
+//! Each type also has a `SCHEMA` hash of its encoding (FNV-1a over the shape of the type:
+//! field types in order, variant names and fields), so master and servant built from
+//! different definitions can tell, see `Hello`.
+
+use core::mem::size_of;
+
+/// Largest `ssmarshal` serialization of a type
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be sent over the wire",
+    note = "derive `WireSize` for your own types, `usize`/`isize`, references and unsized types are not supported"
+)]
+pub trait WireSize {
+    /// Largest number of bytes produced by `ssmarshal::serialize`
+    const MAX_WIRE_SIZE: usize;
+    /// Largest frame produced by `serialize_crc_cobs`, including crc, cobs overhead and delimiter
+    const MAX_FRAME_SIZE: usize = corncobs::max_encoded_len(Self::MAX_WIRE_SIZE + size_of::<u32>());
+    /// Hash of the encoding, equal for types encoded alike
+    const SCHEMA: u32;
+}
+
+/// Buffer type and constructor for frames of a (non-generic) type
+pub trait Frame: WireSize {
+    /// `[u8; Self::MAX_FRAME_SIZE]`
+    type FrameBuf;
+
+    fn frame_buf() -> Self::FrameBuf;
+}
+
+#[doc(hidden)]
+pub const fn const_max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+#[doc(hidden)]
+pub const SCHEMA_SEED: u32 = 0x811c_9dc5;
+
+/// FNV-1a hash of `bytes`, continuing from `hash` (start with `SCHEMA_SEED`)
+#[doc(hidden)]
+pub const fn schema_hash(hash: u32, bytes: &[u8]) -> u32 {
+    let mut hash = hash;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Hash of `value` (e.g., the `SCHEMA` of a field), continuing from `hash`
+#[doc(hidden)]
+pub const fn schema_mix(hash: u32, value: u32) -> u32 {
+    schema_hash(hash, &value.to_le_bytes())
+}
+
+macro_rules! wire_size {
+    ($($t:ty => $size:expr),* $(,)?) => {
+        $(
+            impl WireSize for $t {
+                const MAX_WIRE_SIZE: usize = $size;
+                const SCHEMA: u32 = schema_hash(SCHEMA_SEED, stringify!($t).as_bytes());
+            }
+        )*
+    };
+}
+
+wire_size! {
+    () => 0,
+    bool => 1,
+    u8 => 1,
+    i8 => 1,
+    u16 => 2,
+    i16 => 2,
+    u32 => 4,
+    i32 => 4,
+    f32 => 4,
+    u64 => 8,
+    i64 => 8,
+    f64 => 8,
+    // utf8 encoded
+    char => 4,
+}
+
+impl<T: WireSize, const N: usize> WireSize for [T; N] {
+    const MAX_WIRE_SIZE: usize = N * T::MAX_WIRE_SIZE;
+    const SCHEMA: u32 = schema_mix(
+        schema_mix(schema_hash(SCHEMA_SEED, b"array"), T::SCHEMA),
+        N as u32,
+    );
+}
+
+// `ssmarshal` encodes the length as an u64
+impl<T: WireSize, const N: usize> WireSize for heapless::Vec<T, N> {
+    const MAX_WIRE_SIZE: usize = size_of::<u64>() + N * T::MAX_WIRE_SIZE;
+    const SCHEMA: u32 = schema_mix(
+        schema_mix(schema_hash(SCHEMA_SEED, b"vec"), T::SCHEMA),
+        N as u32,
+    );
+}
+
+impl<T: WireSize> WireSize for Option<T> {
+    const MAX_WIRE_SIZE: usize = 1 + T::MAX_WIRE_SIZE;
+    const SCHEMA: u32 = schema_mix(schema_hash(SCHEMA_SEED, b"option"), T::SCHEMA);
+}
+
+macro_rules! wire_size_tuple {
+    ($($t:ident)+) => {
+        impl<$($t: WireSize),+> WireSize for ($($t,)+) {
+            const MAX_WIRE_SIZE: usize = 0 $(+ $t::MAX_WIRE_SIZE)+;
+            const SCHEMA: u32 = {
+                let hash = schema_hash(SCHEMA_SEED, b"tuple");
+                $(let hash = schema_mix(hash, $t::SCHEMA);)+
+                hash
+            };
+        }
+    };
+}
+
+wire_size_tuple!(A);
+wire_size_tuple!(A B);
+wire_size_tuple!(A B C);
+wire_size_tuple!(A B C D);
+wire_size_tuple!(A B C D E);
+wire_size_tuple!(A B C D E F);
+wire_size_tuple!(A B C D E F G);
+wire_size_tuple!(A B C D E F G H);
@@ -0,0 +1,7 @@
+//! The `WireSize` derive rejects types violating the `ssmarshal` invariants at compile time
+
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
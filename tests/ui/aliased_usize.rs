@@ -0,0 +1,10 @@
+use master_and_servant::WireSize;
+
+type Count = usize;
+
+#[derive(WireSize)]
+struct Counter {
+    count: Count,
+}
+
+fn main() {}
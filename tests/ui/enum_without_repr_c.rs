@@ -0,0 +1,9 @@
+use master_and_servant::WireSize;
+
+#[derive(WireSize)]
+enum Mode {
+    Off,
+    On(u8),
+}
+
+fn main() {}
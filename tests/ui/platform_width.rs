@@ -0,0 +1,15 @@
+use master_and_servant::WireSize;
+
+#[derive(WireSize)]
+struct Len {
+    len: usize,
+    offsets: [isize; 4],
+}
+
+#[derive(WireSize)]
+#[repr(C)]
+enum Pair {
+    Both((u8, usize)),
+}
+
+fn main() {}
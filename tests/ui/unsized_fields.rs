@@ -0,0 +1,28 @@
+use master_and_servant::WireSize;
+
+#[derive(WireSize)]
+struct Reference<'a> {
+    value: &'a u32,
+}
+
+#[derive(WireSize)]
+struct Pointer(*const u8);
+
+#[derive(WireSize)]
+struct Slice {
+    data: [u8],
+}
+
+#[derive(WireSize)]
+struct Str {
+    text: str,
+}
+
+#[derive(WireSize)]
+#[repr(C)]
+enum Nested {
+    Text(&'static str),
+    Words(Option<[&'static [u8]; 2]>),
+}
+
+fn main() {}
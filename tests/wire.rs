@@ -0,0 +1,67 @@
+//! Wire sizes and schema hashes of derived types
+
+use master_and_servant::{Reply, Request, WireSize, SCHEMA};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, WireSize)]
+struct Sample {
+    id: u32,
+    value: Option<i16>,
+    data: [u8; 4],
+}
+
+#[derive(Serialize, Deserialize, WireSize)]
+#[repr(C)]
+enum Event {
+    Started,
+    Sample(Sample),
+    Stopped { code: u8, reason: (u16, bool) },
+}
+
+/// Same shape as `Event`, different variant name
+#[derive(Serialize, Deserialize, WireSize)]
+#[repr(C)]
+enum Renamed {
+    Started,
+    Sample(Sample),
+    Halted { code: u8, reason: (u16, bool) },
+}
+
+/// Same fields as `Sample`, in another order
+#[derive(Serialize, Deserialize, WireSize)]
+struct Reordered {
+    value: Option<i16>,
+    id: u32,
+    data: [u8; 4],
+}
+
+#[test]
+fn wire_size() {
+    assert_eq!(Sample::MAX_WIRE_SIZE, 4 + 3 + 4);
+    assert_eq!(Event::MAX_WIRE_SIZE, 1 + 11);
+}
+
+#[test]
+fn schema_is_stable() {
+    // the hashes are exchanged by `Hello`, changing them breaks compatibility with
+    // deployed servants
+    assert_eq!(Sample::SCHEMA, 0xab82_8422);
+    assert_eq!(Event::SCHEMA, 0xa90a_2bae);
+}
+
+#[test]
+fn schema_follows_shape() {
+    assert_ne!(Event::SCHEMA, Renamed::SCHEMA);
+    assert_ne!(Sample::SCHEMA, Reordered::SCHEMA);
+    assert_ne!(<(u8, u16)>::SCHEMA, <(u16, u8)>::SCHEMA);
+    assert_ne!(<[u8; 4]>::SCHEMA, <[u8; 5]>::SCHEMA);
+    assert_eq!(<Option<Sample>>::SCHEMA, <Option<Sample>>::SCHEMA);
+}
+
+#[test]
+fn protocol_schema_is_pinned() {
+    // update (and bump `PROTOCOL` as appropriate) when changing `Request` or `Reply`
+    assert_eq!(Request::SCHEMA, 0x15ec_b5d5);
+    assert_eq!(Reply::SCHEMA, 0x3bea_6659);
+    assert_eq!(SCHEMA, 0x35c0_37cd);
+}